@@ -0,0 +1,116 @@
+//! AML definition blocks
+//!
+//! There's no AML interpreter here. The few objects we need from the DSDT and
+//! SSDTs are simple enough to be found by scanning the byte code for the
+//! pattern the ASL compilers emit for them.
+
+use crate::acpi::{ Signature, SdtHeader, Table };
+
+/// NameOp, defining a named object
+const NAME_OP: u8 = 0x08;
+
+/// Prefix of a name relative to the root of the namespace, `\`
+const ROOT_PREFIX: u8 = 0x5C;
+
+/// PackageOp, defining a package of objects
+const PACKAGE_OP: u8 = 0x12;
+
+/// ZeroOp, the constant 0
+const ZERO_OP: u8 = 0x00;
+
+/// OneOp, the constant 1
+const ONE_OP: u8 = 0x01;
+
+/// OnesOp, the constant with all bits set
+const ONES_OP: u8 = 0xFF;
+
+/// Prefix of an 8-bit integer
+const BYTE_PREFIX: u8 = 0x0A;
+
+/// Prefix of a 16-bit integer
+const WORD_PREFIX: u8 = 0x0B;
+
+/// Prefix of a 32-bit integer
+const DWORD_PREFIX: u8 = 0x0C;
+
+/// Prefix of a 64-bit integer
+const QWORD_PREFIX: u8 = 0x0E;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+/// Differentiated System Description Table, the main definition block
+pub struct Dsdt {
+    /// The table header
+    pub header: SdtHeader,
+}
+
+unsafe impl Table for Dsdt {
+    const SIGNATURE: Signature = Signature(*b"DSDT");
+}
+
+impl Dsdt {
+    /// Returns the AML byte code of the table
+    pub fn aml(&self) -> &[u8] {
+        &self.bytes()[size_of::<SdtHeader>()..]
+    }
+}
+
+/// Signature of the Secondary System Description Tables, definition blocks
+/// adding to the DSDT
+pub const SSDT: Signature = Signature(*b"SSDT");
+
+/// Decode the integer constant at `offset` in `aml`. Returns its value and the
+/// offset of the byte following it.
+fn integer(aml: &[u8], offset: usize) -> Option<(u64, usize)> {
+    let bytes = |len: usize| -> Option<u64> {
+        let bytes = aml.get(offset + 1..offset + 1 + len)?;
+        Some(bytes.iter().rev().fold(0, |val, &byte| val << 8 | byte as u64))
+    };
+
+    Some(match *aml.get(offset)? {
+        ZERO_OP      => (0, offset + 1),
+        ONE_OP       => (1, offset + 1),
+        ONES_OP      => (!0, offset + 1),
+        BYTE_PREFIX  => (bytes(1)?, offset + 2),
+        WORD_PREFIX  => (bytes(2)?, offset + 3),
+        DWORD_PREFIX => (bytes(4)?, offset + 5),
+        QWORD_PREFIX => (bytes(8)?, offset + 9),
+        _            => return None,
+    })
+}
+
+/// Returns the SLP_TYPa and SLP_TYPb values of the sleep state `\_Sx`, e.g. 5
+/// for soft-off, from the AML byte code `aml`.
+///
+/// Only finds the package the way ASL compilers emit
+/// `Name (_S5, Package () { a, b, ... })`; computed packages are missed.
+pub fn sleep_type(aml: &[u8], state: u8) -> Option<(u8, u8)> {
+    let name = [b'_', b'S', b'0' + state, b'_'];
+
+    aml.windows(name.len()).enumerate()
+        .filter(|(_, window)| *window == name)
+        .find_map(|(pos, _)| {
+            // It has to be a named object, optionally relative to the root
+            let named = match pos.checked_sub(1).map(|pos| aml[pos]) {
+                Some(NAME_OP)     => true,
+                Some(ROOT_PREFIX) => pos >= 2 && aml[pos - 2] == NAME_OP,
+                _                 => false,
+            };
+            if !named { return None; }
+
+            // Which is a package
+            let mut offset = pos + name.len();
+            if *aml.get(offset)? != PACKAGE_OP { return None; }
+            offset += 1;
+
+            // Skip the package length; the top two bits of its lead byte
+            // count the bytes following it. Then skip the number of elements
+            let lead = *aml.get(offset)?;
+            offset += 1 + (lead >> 6) as usize + 1;
+
+            // SLP_TYPa, followed by SLP_TYPb, which some firmware leaves out
+            let (slp_typa, offset) = integer(aml, offset)?;
+            let slp_typb = integer(aml, offset).map_or(0, |(val, _)| val);
+            Some((slp_typa as u8, slp_typb as u8))
+        })
+}
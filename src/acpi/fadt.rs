@@ -0,0 +1,230 @@
+//! Fixed ACPI Description Table
+//!
+//! Describes the fixed hardware of the platform: the power management
+//! registers, the reset register and where the DSDT is. The structure only
+//! covers the ACPI 1.0 part of the table; the fields added later are read
+//! from the bytes of the table if it's long enough to have them.
+
+use core::mem::size_of;
+use crate::acpi::{ self, Error, GenericAddress, Signature, SdtHeader,
+                   Table };
+use crate::acpi::aml::Dsdt;
+
+/// The reset register is supported
+pub const RESET_REG_SUP: u32 = 1 << 10;
+
+/// Enables the generation of SCIs instead of SMIs, i.e. the ACPI mode
+pub const SCI_EN: u16 = 1 << 0;
+
+/// Shift of the SLP_TYP field in the PM1 control registers
+pub const SLP_TYP_SHIFT: u16 = 10;
+
+/// Mask of the SLP_TYP field in the PM1 control registers
+pub const SLP_TYP_MASK: u16 = 0b111 << SLP_TYP_SHIFT;
+
+/// Makes the system enter the sleep state in SLP_TYP
+pub const SLP_EN: u16 = 1 << 13;
+
+/// Offset of the reset register in the table
+const RESET_REG: usize = 116;
+
+/// Offset of the value to write to the reset register in the table
+const RESET_VALUE: usize = 128;
+
+/// Offset of the 64-bit address of the DSDT in the table
+const X_DSDT: usize = 140;
+
+/// Offset of the extended PM1a control register block in the table
+const X_PM1A_CNT_BLK: usize = 172;
+
+/// Offset of the extended PM1b control register block in the table
+const X_PM1B_CNT_BLK: usize = 184;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+/// The ACPI 1.0 part of the FADT
+pub struct Fadt {
+    /// The table header
+    pub header: SdtHeader,
+
+    /// Physical address of the FACS
+    pub firmware_ctrl: u32,
+
+    /// Physical address of the DSDT
+    pub dsdt: u32,
+
+    /// Reserved
+    reserved0: u8,
+
+    /// Preferred power management profile
+    pub preferred_pm_profile: u8,
+
+    /// Interrupt the SCI is wired to in 8259 mode
+    pub sci_int: u16,
+
+    /// I/O port of the SMI command port
+    pub smi_cmd: u32,
+
+    /// Value to write to `smi_cmd` to enter the ACPI mode
+    pub acpi_enable: u8,
+
+    /// Value to write to `smi_cmd` to leave the ACPI mode
+    pub acpi_disable: u8,
+
+    /// Value to write to `smi_cmd` to enter the S4BIOS state
+    pub s4bios_req: u8,
+
+    /// Value to write to `smi_cmd` to take over processor performance control
+    pub pstate_cnt: u8,
+
+    /// I/O port of the PM1a event register block
+    pub pm1a_evt_blk: u32,
+
+    /// I/O port of the PM1b event register block
+    pub pm1b_evt_blk: u32,
+
+    /// I/O port of the PM1a control register block
+    pub pm1a_cnt_blk: u32,
+
+    /// I/O port of the PM1b control register block
+    pub pm1b_cnt_blk: u32,
+
+    /// I/O port of the PM2 control register block
+    pub pm2_cnt_blk: u32,
+
+    /// I/O port of the power management timer
+    pub pm_tmr_blk: u32,
+
+    /// I/O port of the general-purpose event 0 register block
+    pub gpe0_blk: u32,
+
+    /// I/O port of the general-purpose event 1 register block
+    pub gpe1_blk: u32,
+
+    /// Length of the PM1 event register blocks in bytes
+    pub pm1_evt_len: u8,
+
+    /// Length of the PM1 control register blocks in bytes
+    pub pm1_cnt_len: u8,
+
+    /// Length of the PM2 control register block in bytes
+    pub pm2_cnt_len: u8,
+
+    /// Length of the power management timer block in bytes
+    pub pm_tmr_len: u8,
+
+    /// Length of the general-purpose event 0 register block in bytes
+    pub gpe0_blk_len: u8,
+
+    /// Length of the general-purpose event 1 register block in bytes
+    pub gpe1_blk_len: u8,
+
+    /// First general-purpose event of the event 1 register block
+    pub gpe1_base: u8,
+
+    /// Value to write to `smi_cmd` to support C states change notifications
+    pub cst_cnt: u8,
+
+    /// Worst-case latency to enter and leave C2 in microseconds
+    pub p_lvl2_lat: u16,
+
+    /// Worst-case latency to enter and leave C3 in microseconds
+    pub p_lvl3_lat: u16,
+
+    /// Number of flush strides to flush the caches with
+    pub flush_size: u16,
+
+    /// Cache line width for flushing the caches
+    pub flush_stride: u16,
+
+    /// Offset of the duty cycle in the processor control register
+    pub duty_offset: u8,
+
+    /// Width of the duty cycle in the processor control register
+    pub duty_width: u8,
+
+    /// RTC CMOS index of the day-of-month alarm
+    pub day_alrm: u8,
+
+    /// RTC CMOS index of the month-of-year alarm
+    pub mon_alrm: u8,
+
+    /// RTC CMOS index of the century
+    pub century: u8,
+
+    /// IA-PC boot architecture flags
+    pub iapc_boot_arch: u16,
+
+    /// Reserved
+    reserved1: u8,
+
+    /// Fixed feature flags, e.g. [`RESET_REG_SUP`]
+    pub flags: u32,
+}
+
+unsafe impl Table for Fadt {
+    const SIGNATURE: Signature = Signature(*b"FACP");
+}
+
+impl Fadt {
+    /// Returns the `T` at `offset` in the table, if the table is long enough
+    /// to have it
+    fn field<T: Copy>(&self, offset: usize) -> Option<T> {
+        let bytes = self.bytes().get(offset..offset + size_of::<T>())?;
+        Some(unsafe { (bytes.as_ptr() as *const T).read_unaligned() })
+    }
+
+    /// Returns the reset register and the value to write to it, if the
+    /// platform supports resetting through it
+    pub fn reset_register(&self) -> Option<(GenericAddress, u8)> {
+        if self.flags & RESET_REG_SUP == 0 { return None; }
+
+        let reg = self.field::<GenericAddress>(RESET_REG)?;
+        let val = self.field::<u8>(RESET_VALUE)?;
+        reg.is_present().then_some((reg, val))
+    }
+
+    /// Returns the control register of the PM1a or, with `b`, the PM1b
+    /// register block, preferring the extended address
+    fn pm1_control(&self, b: bool) -> Option<GenericAddress> {
+        let (extended, legacy) = if b {
+            (X_PM1B_CNT_BLK, self.pm1b_cnt_blk)
+        } else {
+            (X_PM1A_CNT_BLK, self.pm1a_cnt_blk)
+        };
+
+        if let Some(reg) = self.field::<GenericAddress>(extended) &&
+                reg.is_present() {
+            return Some(reg);
+        }
+
+        // The legacy blocks are always I/O ports accessed as words
+        (legacy != 0).then_some(GenericAddress {
+            address_space: acpi::ADDRESS_SPACE_IO,
+            bit_width:     16,
+            bit_offset:    0,
+            access_size:   2,
+            address:       legacy as u64,
+        })
+    }
+
+    /// Returns the PM1a control register
+    pub fn pm1a_control(&self) -> Option<GenericAddress> {
+        self.pm1_control(false)
+    }
+
+    /// Returns the PM1b control register, if there's a PM1b register block
+    pub fn pm1b_control(&self) -> Option<GenericAddress> {
+        self.pm1_control(true)
+    }
+
+    /// Returns the validated DSDT, preferring its 64-bit address
+    pub fn dsdt(&self) -> Result<&'static Dsdt, Error> {
+        let addr = self.field::<u64>(X_DSDT)
+            .filter(|&addr| addr != 0)
+            .unwrap_or(self.dsdt as u64);
+        if addr == 0 { return Err(Error::NotFound(Dsdt::SIGNATURE)); }
+
+        unsafe { acpi::validate(addr as usize as *const SdtHeader) }
+    }
+}
@@ -0,0 +1,32 @@
+//! High Precision Event Timer table
+//!
+//! Tells us where the registers of the HPET are.
+
+use crate::acpi::{ GenericAddress, Signature, SdtHeader, Table };
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+/// The HPET table
+pub struct HpetTable {
+    /// The table header
+    pub header: SdtHeader,
+
+    /// Copy of the low 32 bits of the capabilities register of the HPET
+    pub event_timer_block_id: u32,
+
+    /// Location of the registers of the HPET, always in system memory
+    pub base_address: GenericAddress,
+
+    /// Sequence number of the HPET
+    pub hpet_number: u8,
+
+    /// Minimum number of ticks a periodic timer can be set to
+    pub minimum_tick: u16,
+
+    /// Page protection and OEM attributes
+    pub page_protection: u8,
+}
+
+unsafe impl Table for HpetTable {
+    const SIGNATURE: Signature = Signature(*b"HPET");
+}
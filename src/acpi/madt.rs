@@ -0,0 +1,376 @@
+//! Multiple APIC Description Table
+//!
+//! Describes the interrupt controllers of the system: a local APIC (or
+//! x2APIC) per processor, the IO APICs and how the legacy ISA interrupts are
+//! wired to them.
+
+use alloc::vec::Vec;
+use crate::acpi::{ Signature, SdtHeader, Table };
+
+/// The MADT describes a dual-8259 setup in addition to the APICs
+pub const PCAT_COMPAT: u32 = 1 << 0;
+
+/// The processor is enabled
+const PROCESSOR_ENABLED: u32 = 1 << 0;
+
+/// The processor can be enabled at runtime
+const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+/// The fixed part of the MADT, followed by the interrupt controller structures
+pub struct Madt {
+    /// The table header
+    pub header: SdtHeader,
+
+    /// Physical address of the local APIC of every processor
+    pub local_apic_address: u32,
+
+    /// `PCAT_COMPAT` flag
+    pub flags: u32,
+}
+
+unsafe impl Table for Madt {
+    const SIGNATURE: Signature = Signature(*b"APIC");
+}
+
+/// Polarity of an interrupt input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Polarity {
+    /// Conforms to the specification of the bus
+    BusDefault,
+
+    /// Active high
+    ActiveHigh,
+
+    /// Active low
+    ActiveLow,
+}
+
+/// Trigger mode of an interrupt input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// Conforms to the specification of the bus
+    BusDefault,
+
+    /// Edge-triggered
+    Edge,
+
+    /// Level-triggered
+    Level,
+}
+
+/// Returns the polarity and trigger mode described by MPS INTI `flags`
+fn inti_flags(flags: u16) -> (Polarity, Trigger) {
+    let polarity = match flags & 0b11 {
+        0b01 => Polarity::ActiveHigh,
+        0b11 => Polarity::ActiveLow,
+        _    => Polarity::BusDefault,
+    };
+    let trigger = match (flags >> 2) & 0b11 {
+        0b01 => Trigger::Edge,
+        0b11 => Trigger::Level,
+        _    => Trigger::BusDefault,
+    };
+    (polarity, trigger)
+}
+
+/// A processor and its local APIC or x2APIC
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    /// ACPI processor UID, matching the processor objects in the namespace
+    pub uid: u32,
+
+    /// ID of the local APIC or x2APIC of the processor
+    pub apic_id: u32,
+
+    /// Whether the processor is enabled
+    pub enabled: bool,
+
+    /// Whether the processor can be enabled at runtime
+    pub online_capable: bool,
+
+    /// Whether the processor was described by an x2APIC structure
+    pub x2apic: bool,
+}
+
+/// An IO APIC
+#[derive(Debug, Clone, Copy)]
+pub struct IoApic {
+    /// ID of the IO APIC
+    pub id: u8,
+
+    /// Physical address of the registers of the IO APIC
+    pub address: u32,
+
+    /// First global system interrupt handled by the IO APIC
+    pub gsi_base: u32,
+}
+
+/// A legacy ISA interrupt not identity-mapped to a global system interrupt
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    /// Bus of the interrupt; 0 for ISA
+    pub bus: u8,
+
+    /// The bus-relative interrupt, i.e. the ISA IRQ
+    pub source: u8,
+
+    /// Global system interrupt the source is wired to
+    pub gsi: u32,
+
+    /// Polarity of the interrupt
+    pub polarity: Polarity,
+
+    /// Trigger mode of the interrupt
+    pub trigger: Trigger,
+}
+
+/// A global system interrupt which should be programmed as an NMI
+#[derive(Debug, Clone, Copy)]
+pub struct NmiSource {
+    /// The global system interrupt
+    pub gsi: u32,
+
+    /// Polarity of the interrupt
+    pub polarity: Polarity,
+
+    /// Trigger mode of the interrupt
+    pub trigger: Trigger,
+}
+
+/// A local APIC LINT input wired to NMI
+#[derive(Debug, Clone, Copy)]
+pub struct LocalNmi {
+    /// ACPI processor UID of the processor, or `None` for all of them
+    pub uid: Option<u32>,
+
+    /// The LINT input of the local APIC, 0 or 1
+    pub lint: u8,
+
+    /// Polarity of the interrupt
+    pub polarity: Polarity,
+
+    /// Trigger mode of the interrupt
+    pub trigger: Trigger,
+}
+
+/// An interrupt controller structure of the MADT
+#[derive(Debug, Clone, Copy)]
+pub enum Entry {
+    /// A processor, from a local APIC or a local x2APIC structure
+    Processor(Processor),
+
+    /// An IO APIC
+    IoApic(IoApic),
+
+    /// An interrupt source override
+    InterruptOverride(InterruptOverride),
+
+    /// An NMI source
+    NmiSource(NmiSource),
+
+    /// A local APIC or local x2APIC NMI
+    LocalNmi(LocalNmi),
+
+    /// A 64-bit local APIC address, overriding the one in the table header
+    LocalApicAddress(u64),
+
+    /// A structure we don't decode
+    Unknown { ty: u8, length: u8 },
+}
+
+/// Returns the little-endian `N` bytes at `offset` in `bytes`
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N].try_into().unwrap()
+}
+
+impl Entry {
+    /// Decode the structure `bytes` of type `ty`. Returns `None` if it's too
+    /// short for its type.
+    fn parse(ty: u8, bytes: &[u8]) -> Option<Self> {
+        // Every structure has a minimal length depending on its type
+        let min = match ty {
+            0 => 8, 1 => 12, 2 => 10, 3 => 8, 4 => 6, 5 => 12, 9 => 16,
+            10 => 12, _ => 2,
+        };
+        if bytes.len() < min { return None; }
+
+        let u16_at = |offset| u16::from_le_bytes(read(bytes, offset));
+        let u32_at = |offset| u32::from_le_bytes(read(bytes, offset));
+
+        Some(match ty {
+            0 => Entry::Processor(Processor {
+                uid:            bytes[2] as u32,
+                apic_id:        bytes[3] as u32,
+                enabled:        u32_at(4) & PROCESSOR_ENABLED != 0,
+                online_capable: u32_at(4) & PROCESSOR_ONLINE_CAPABLE != 0,
+                x2apic:         false,
+            }),
+            1 => Entry::IoApic(IoApic {
+                id:       bytes[2],
+                address:  u32_at(4),
+                gsi_base: u32_at(8),
+            }),
+            2 => {
+                let (polarity, trigger) = inti_flags(u16_at(8));
+                Entry::InterruptOverride(InterruptOverride {
+                    bus: bytes[2], source: bytes[3], gsi: u32_at(4),
+                    polarity, trigger,
+                })
+            },
+            3 => {
+                let (polarity, trigger) = inti_flags(u16_at(2));
+                Entry::NmiSource(NmiSource {
+                    gsi: u32_at(4), polarity, trigger,
+                })
+            },
+            4 => {
+                let (polarity, trigger) = inti_flags(u16_at(3));
+                Entry::LocalNmi(LocalNmi {
+                    uid: (bytes[2] != 0xFF).then_some(bytes[2] as u32),
+                    lint: bytes[5],
+                    polarity, trigger,
+                })
+            },
+            5 => Entry::LocalApicAddress(u64::from_le_bytes(read(bytes, 4))),
+            9 => Entry::Processor(Processor {
+                uid:            u32_at(12),
+                apic_id:        u32_at(4),
+                enabled:        u32_at(8) & PROCESSOR_ENABLED != 0,
+                online_capable: u32_at(8) & PROCESSOR_ONLINE_CAPABLE != 0,
+                x2apic:         true,
+            }),
+            10 => {
+                let (polarity, trigger) = inti_flags(u16_at(2));
+                Entry::LocalNmi(LocalNmi {
+                    uid: (u32_at(4) != 0xFFFF_FFFF).then_some(u32_at(4)),
+                    lint: bytes[8],
+                    polarity, trigger,
+                })
+            },
+            _ => Entry::Unknown { ty, length: bytes.len() as u8 },
+        })
+    }
+}
+
+impl Madt {
+    /// Returns an iterator over the interrupt controller structures. Decoding
+    /// stops at the first malformed structure.
+    pub fn entries(&self) -> impl Iterator<Item = Entry> + '_ {
+        let bytes = self.bytes();
+        let mut offset = size_of::<Madt>();
+
+        core::iter::from_fn(move || {
+            // Every structure starts with its type and length
+            let header = bytes.get(offset..offset + 2)?;
+            let (ty, length) = (header[0], header[1] as usize);
+            let entry = bytes.get(offset..offset + length)?;
+
+            let entry = Entry::parse(ty, entry)?;
+            offset += length;
+            Some(entry)
+        })
+    }
+
+    /// Decode the whole table into a [`Topology`].
+    ///
+    /// This allocates, so it's only available once the memory manager is up.
+    pub fn topology(&self) -> Topology {
+        let mut topology = Topology {
+            local_apic_address: self.local_apic_address as u64,
+            pcat_compat:        self.flags & PCAT_COMPAT != 0,
+            processors:         Vec::new(),
+            io_apics:           Vec::new(),
+            overrides:          Vec::new(),
+            nmi_sources:        Vec::new(),
+            local_nmis:         Vec::new(),
+        };
+
+        for entry in self.entries() {
+            match entry {
+                Entry::Processor(cpu) => topology.processors.push(cpu),
+                Entry::IoApic(io_apic) => topology.io_apics.push(io_apic),
+                Entry::InterruptOverride(over) => topology.overrides.push(over),
+                Entry::NmiSource(nmi) => topology.nmi_sources.push(nmi),
+                Entry::LocalNmi(nmi) => topology.local_nmis.push(nmi),
+                Entry::LocalApicAddress(addr) => {
+                    topology.local_apic_address = addr;
+                },
+                Entry::Unknown { .. } => {},
+            }
+        }
+        topology
+    }
+}
+
+/// The interrupt controllers of the system, as described by the MADT
+#[derive(Debug, Clone)]
+pub struct Topology {
+    /// Physical address of the local APIC of every processor
+    pub local_apic_address: u64,
+
+    /// Whether there's a dual-8259 setup which has to be disabled before
+    /// using the APICs
+    pub pcat_compat: bool,
+
+    /// The processors, in the order the firmware wants them started
+    pub processors: Vec<Processor>,
+
+    /// The IO APICs
+    pub io_apics: Vec<IoApic>,
+
+    /// Legacy interrupts not identity-mapped to global system interrupts
+    pub overrides: Vec<InterruptOverride>,
+
+    /// Global system interrupts which should be programmed as NMIs
+    pub nmi_sources: Vec<NmiSource>,
+
+    /// Local APIC inputs wired to NMI
+    pub local_nmis: Vec<LocalNmi>,
+}
+
+impl Topology {
+    /// Returns an iterator over the enabled processors
+    pub fn enabled_processors(&self) -> impl Iterator<Item = &Processor> {
+        self.processors.iter().filter(|cpu| cpu.enabled)
+    }
+
+    /// Returns the processor with the local APIC ID `apic_id`
+    pub fn processor(&self, apic_id: u32) -> Option<&Processor> {
+        self.processors.iter().find(|cpu| cpu.apic_id == apic_id)
+    }
+
+    /// Returns the IO APIC handling the global system interrupt `gsi`. The
+    /// number of inputs of an IO APIC is only known from its registers, so
+    /// this is the IO APIC with the closest `gsi_base` below `gsi`.
+    pub fn io_apic(&self, gsi: u32) -> Option<&IoApic> {
+        self.io_apics.iter()
+            .filter(|io_apic| io_apic.gsi_base <= gsi)
+            .max_by_key(|io_apic| io_apic.gsi_base)
+    }
+
+    /// Returns the global system interrupt the ISA `irq` is wired to, along
+    /// with its polarity and trigger mode. ISA interrupts without an override
+    /// are identity-mapped, active high and edge-triggered.
+    pub fn isa_irq(&self, irq: u8) -> (u32, Polarity, Trigger) {
+        let over = self.overrides.iter()
+            .find(|over| over.bus == 0 && over.source == irq);
+
+        match over {
+            Some(over) => {
+                // Bus defaults are the ISA defaults
+                let polarity = match over.polarity {
+                    Polarity::BusDefault => Polarity::ActiveHigh,
+                    polarity             => polarity,
+                };
+                let trigger = match over.trigger {
+                    Trigger::BusDefault => Trigger::Edge,
+                    trigger             => trigger,
+                };
+                (over.gsi, polarity, trigger)
+            },
+            None => (irq as u32, Polarity::ActiveHigh, Trigger::Edge),
+        }
+    }
+}
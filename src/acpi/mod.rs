@@ -0,0 +1,524 @@
+//! ACPI tables
+//!
+//! The RSDP is found through the EFI configuration tables. It points to the
+//! XSDT, or the RSDT on ACPI 1.0 systems, which in turn lists every other
+//! system description table. Checksums are verified before a table is handed
+//! out, so a corrupted table is an error instead of garbage.
+
+use core::fmt;
+use core::mem::size_of;
+use crate::{ cpu, efi };
+
+pub mod madt;
+pub mod spcr;
+pub mod fadt;
+pub mod aml;
+pub mod hpet;
+
+/// Errors returned by the ACPI routines
+#[derive(Debug)]
+pub enum Error {
+    /// The firmware doesn't point us to an RSDP
+    NoRsdp,
+
+    /// The RSDP doesn't start with "RSD PTR "
+    WrongRsdpSignature,
+
+    /// The checksum of the RSDP is wrong
+    WrongRsdpChecksum,
+
+    /// The table has a signature different from the one it should have
+    WrongSignature { expected: Signature, found: Signature },
+
+    /// The table is shorter than its header or the structure we access it
+    /// through
+    WrongLength { signature: Signature, length: u32 },
+
+    /// The checksum of the table is wrong
+    WrongChecksum(Signature),
+
+    /// There's no table with the signature
+    NotFound(Signature),
+
+    /// A register lives in an address space or has an access width we don't
+    /// support
+    UnsupportedRegister { address_space: u8, width: usize },
+}
+
+/// Signature of a system description table, e.g. `APIC` for the MADT
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Signature(pub [u8; 4]);
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|&byte| {
+            let c = if byte.is_ascii_graphic() { byte as char } else { '?' };
+            write!(f, "{}", c)
+        })
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+/// Root System Description Pointer
+pub struct Rsdp {
+    /// "RSD PTR "
+    pub signature: [u8; 8],
+
+    /// Makes the sum of the first 20 bytes 0
+    pub checksum: u8,
+
+    /// OEM-supplied string identifying the OEM
+    pub oem_id: [u8; 6],
+
+    /// 0 for ACPI 1.0, 2 for ACPI 2.0 and newer
+    pub revision: u8,
+
+    /// Physical address of the RSDT
+    pub rsdt_address: u32,
+
+    /// Length of the whole table; ACPI 2.0 and newer only
+    pub length: u32,
+
+    /// Physical address of the XSDT; ACPI 2.0 and newer only
+    pub xsdt_address: u64,
+
+    /// Makes the sum of the whole table 0; ACPI 2.0 and newer only
+    pub extended_checksum: u8,
+
+    /// Reserved
+    reserved: [u8; 3],
+}
+
+/// Offset of `length` in the RSDP, the size of the ACPI 1.0 structure
+const RSDP_V1_SIZE: usize = 20;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+/// Header every system description table starts with
+pub struct SdtHeader {
+    /// Signature identifying the table
+    pub signature: Signature,
+
+    /// Length of the whole table in bytes, including the header
+    pub length: u32,
+
+    /// Revision of the structure of the table
+    pub revision: u8,
+
+    /// Makes the sum of the whole table 0
+    pub checksum: u8,
+
+    /// OEM-supplied string identifying the OEM
+    pub oem_id: [u8; 6],
+
+    /// OEM-supplied string identifying the table
+    pub oem_table_id: [u8; 8],
+
+    /// OEM-supplied revision number of the table
+    pub oem_revision: u32,
+
+    /// Vendor ID of the utility that created the table
+    pub creator_id: u32,
+
+    /// Revision of the utility that created the table
+    pub creator_revision: u32,
+}
+
+/// Address space of a [`GenericAddress`] in system memory
+pub const ADDRESS_SPACE_MEMORY: u8 = 0;
+
+/// Address space of a [`GenericAddress`] in system I/O space
+pub const ADDRESS_SPACE_IO: u8 = 1;
+
+/// Address space of a [`GenericAddress`] in the PCI configuration space of a
+/// device on bus 0
+pub const ADDRESS_SPACE_PCI_CONFIG: u8 = 2;
+
+/// I/O port selecting the PCI configuration space register to access
+const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
+
+/// I/O port through which the selected PCI configuration register is accessed
+const PCI_CONFIG_DATA: u16 = 0xCFC;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+/// Generic Address Structure, describing where a register lives and how it's
+/// accessed
+pub struct GenericAddress {
+    /// One of the `ADDRESS_SPACE_*` values
+    pub address_space: u8,
+
+    /// Size of the register in bits
+    pub bit_width: u8,
+
+    /// Offset of the register in bits from `address`
+    pub bit_offset: u8,
+
+    /// 0 if undefined, otherwise 1 for byte, 2 for word, 3 for dword and 4
+    /// for qword accesses
+    pub access_size: u8,
+
+    /// Address of the register in `address_space`
+    pub address: u64,
+}
+
+impl GenericAddress {
+    /// Returns whether the structure describes a register at all
+    pub fn is_present(&self) -> bool {
+        let address = self.address;
+        address != 0
+    }
+
+    /// Returns the width of an access to the register in bytes. Falls back
+    /// to the width of the register if the access size is undefined.
+    pub fn access_bytes(&self) -> usize {
+        match self.access_size {
+            size @ 1..=4 => 1 << (size - 1),
+            _            => (self.bit_width as usize / 8).max(1),
+        }
+    }
+
+    /// Returns the error for accesses we don't support
+    fn unsupported(&self) -> Error {
+        Error::UnsupportedRegister {
+            address_space: self.address_space,
+            width:         self.access_bytes(),
+        }
+    }
+
+    /// Select the PCI configuration register and returns the data port to
+    /// access it through
+    ///
+    /// # Safety
+    ///
+    /// Nobody else may use the PCI configuration ports until the register is
+    /// accessed through the returned port.
+    unsafe fn select_pci_config(&self) -> *const u16 {
+        // The address is device << 32 | function << 16 | offset
+        let addr = self.address;
+        let device = (addr >> 32) as u32 & 0x1F;
+        let function = (addr >> 16) as u32 & 0x7;
+        let offset = addr as u32 & 0xFF;
+
+        unsafe {
+            cpu::out32(PCI_CONFIG_ADDRESS as *const u16,
+                       0x8000_0000 | device << 11 | function << 8 |
+                       (offset & !3));
+        }
+        (PCI_CONFIG_DATA + (offset & 3) as u16) as *const u16
+    }
+
+    /// Read the register. The bit offset is ignored, so the register is read
+    /// as a whole.
+    ///
+    /// # Safety
+    ///
+    /// The register must be one the firmware described, and reading it must
+    /// not interfere with whoever else owns the hardware behind it.
+    pub unsafe fn read(&self) -> Result<u64, Error> {
+        let addr = self.address as usize;
+        let port = addr as u16 as *const u16;
+        unsafe {
+            Ok(match (self.address_space, self.access_bytes()) {
+                (ADDRESS_SPACE_MEMORY, 1) => {
+                    (addr as *const u8).read_volatile() as u64
+                },
+                (ADDRESS_SPACE_MEMORY, 2) => {
+                    (addr as *const u16).read_volatile() as u64
+                },
+                (ADDRESS_SPACE_MEMORY, 4) => {
+                    (addr as *const u32).read_volatile() as u64
+                },
+                (ADDRESS_SPACE_MEMORY, 8) => {
+                    (addr as *const u64).read_volatile()
+                },
+                (ADDRESS_SPACE_IO, 1) => cpu::in8(port) as u64,
+                (ADDRESS_SPACE_IO, 2) => cpu::in16(port) as u64,
+                (ADDRESS_SPACE_IO, 4) => cpu::in32(port) as u64,
+                (ADDRESS_SPACE_PCI_CONFIG, 1) => {
+                    cpu::in8(self.select_pci_config()) as u64
+                },
+                (ADDRESS_SPACE_PCI_CONFIG, 2) => {
+                    cpu::in16(self.select_pci_config()) as u64
+                },
+                (ADDRESS_SPACE_PCI_CONFIG, 4) => {
+                    cpu::in32(self.select_pci_config()) as u64
+                },
+                _ => return Err(self.unsupported()),
+            })
+        }
+    }
+
+    /// Write `val` to the register, truncated to the access width. The bit
+    /// offset is ignored, so the register is written as a whole.
+    ///
+    /// # Safety
+    ///
+    /// See [`GenericAddress::read()`]. Writing may do anything the hardware
+    /// behind the register does, including resetting the system.
+    pub unsafe fn write(&self, val: u64) -> Result<(), Error> {
+        let addr = self.address as usize;
+        let port = addr as u16 as *const u16;
+        unsafe {
+            match (self.address_space, self.access_bytes()) {
+                (ADDRESS_SPACE_MEMORY, 1) => {
+                    (addr as *mut u8).write_volatile(val as u8)
+                },
+                (ADDRESS_SPACE_MEMORY, 2) => {
+                    (addr as *mut u16).write_volatile(val as u16)
+                },
+                (ADDRESS_SPACE_MEMORY, 4) => {
+                    (addr as *mut u32).write_volatile(val as u32)
+                },
+                (ADDRESS_SPACE_MEMORY, 8) => {
+                    (addr as *mut u64).write_volatile(val)
+                },
+                (ADDRESS_SPACE_IO, 1) => cpu::out8(port, val as u8),
+                (ADDRESS_SPACE_IO, 2) => cpu::out16(port, val as u16),
+                (ADDRESS_SPACE_IO, 4) => cpu::out32(port, val as u32),
+                (ADDRESS_SPACE_PCI_CONFIG, 1) => {
+                    cpu::out8(self.select_pci_config(), val as u8)
+                },
+                (ADDRESS_SPACE_PCI_CONFIG, 2) => {
+                    cpu::out16(self.select_pci_config(), val as u16)
+                },
+                (ADDRESS_SPACE_PCI_CONFIG, 4) => {
+                    cpu::out32(self.select_pci_config(), val as u32)
+                },
+                _ => return Err(self.unsupported()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A system description table, beginning with an [`SdtHeader`]. The structure
+/// only has to describe the fixed part of the table; the rest is reachable
+/// through [`Table::bytes()`].
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C, packed)]` structures starting with an
+/// [`SdtHeader`] and laid out like the table with `SIGNATURE`.
+pub unsafe trait Table: Sized {
+    /// Signature of the table
+    const SIGNATURE: Signature;
+
+    /// Returns the header of the table
+    fn header(&self) -> &SdtHeader {
+        unsafe { &*(self as *const Self as *const SdtHeader) }
+    }
+
+    /// Returns the whole table, including the header
+    fn bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self as *const Self as *const u8,
+                                        self.header().length as usize)
+        }
+    }
+}
+
+/// Returns the 8-bit sum of `bytes`, which is 0 for a valid table
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Validate the length and checksum of the table at `table`
+///
+/// # Safety
+///
+/// `table` must point to readable memory holding an [`SdtHeader`], and as
+/// many bytes as the header claims. The table must never be freed.
+pub unsafe fn validate_sdt(table: *const SdtHeader)
+        -> Result<&'static SdtHeader, Error> {
+    let hdr = unsafe { &*table };
+
+    // Make sure there's at least a header
+    if (hdr.length as usize) < size_of::<SdtHeader>() {
+        return Err(Error::WrongLength {
+            signature: hdr.signature,
+            length:    hdr.length,
+        });
+    }
+
+    let bytes = unsafe {
+        core::slice::from_raw_parts(table as *const u8, hdr.length as usize)
+    };
+    if checksum(bytes) != 0 {
+        return Err(Error::WrongChecksum(hdr.signature));
+    }
+
+    Ok(hdr)
+}
+
+/// Validate the signature, length and checksum of the table at `table`
+///
+/// # Safety
+///
+/// See [`validate_sdt()`].
+pub unsafe fn validate<T: Table>(table: *const SdtHeader)
+        -> Result<&'static T, Error> {
+    let hdr = unsafe { validate_sdt(table)? };
+
+    if hdr.signature != T::SIGNATURE {
+        return Err(Error::WrongSignature {
+            expected: T::SIGNATURE,
+            found:    hdr.signature,
+        });
+    }
+
+    // Make sure the table is as large as the structure we access it through
+    if (hdr.length as usize) < size_of::<T>() {
+        return Err(Error::WrongLength {
+            signature: hdr.signature,
+            length:    hdr.length,
+        });
+    }
+
+    Ok(unsafe { &*(table as *const T) })
+}
+
+/// The ACPI tables of the system
+#[derive(Debug, Clone, Copy)]
+pub struct Acpi {
+    /// The RSDP
+    rsdp: &'static Rsdp,
+
+    /// The XSDT or RSDT
+    root: &'static SdtHeader,
+
+    /// Size of an entry in `root`; 8 for the XSDT, 4 for the RSDT
+    entry_size: usize,
+}
+
+impl Acpi {
+    /// Find the ACPI tables through the EFI configuration tables, preferring
+    /// the ACPI 2.0 RSDP
+    pub fn new() -> Result<Self, Error> {
+        let rsdp = efi::find_config_table(&efi::guid::ACPI_20_TABLE)
+            .or_else(|| efi::find_config_table(&efi::guid::ACPI_TABLE))
+            .ok_or(Error::NoRsdp)?;
+        unsafe { Self::from_rsdp(rsdp as *const Rsdp) }
+    }
+
+    /// Validate the RSDP at `rsdp` and the XSDT or RSDT it points to
+    ///
+    /// # Safety
+    ///
+    /// `rsdp` must be null or point to a readable RSDP, as long as its
+    /// revision says, whose tables meet the requirements of
+    /// [`validate_sdt()`]. None of them may ever be freed.
+    pub unsafe fn from_rsdp(rsdp: *const Rsdp) -> Result<Self, Error> {
+        if rsdp.is_null() { return Err(Error::NoRsdp); }
+        let rsdp_ref = unsafe { &*rsdp };
+
+        if &rsdp_ref.signature != b"RSD PTR " {
+            return Err(Error::WrongRsdpSignature);
+        }
+
+        // The first 20 bytes are checksummed on their own, the whole extended
+        // structure again on ACPI 2.0 and newer
+        let bytes = |len| unsafe {
+            core::slice::from_raw_parts(rsdp as *const u8, len)
+        };
+        if checksum(bytes(RSDP_V1_SIZE)) != 0 {
+            return Err(Error::WrongRsdpChecksum);
+        }
+        let extended = rsdp_ref.revision >= 2;
+        if extended && ((rsdp_ref.length as usize) < size_of::<Rsdp>() ||
+                        checksum(bytes(rsdp_ref.length as usize)) != 0) {
+            return Err(Error::WrongRsdpChecksum);
+        }
+
+        // Prefer the XSDT, which can point above 4 GiB
+        let (root, signature, entry_size) =
+            if extended && rsdp_ref.xsdt_address != 0 {
+                (rsdp_ref.xsdt_address as usize, Signature(*b"XSDT"), 8)
+            } else {
+                (rsdp_ref.rsdt_address as usize, Signature(*b"RSDT"), 4)
+            };
+
+        let root = unsafe { validate_sdt(root as *const SdtHeader)? };
+        if root.signature != signature {
+            return Err(Error::WrongSignature {
+                expected: signature,
+                found:    root.signature,
+            });
+        }
+
+        Ok(Self { rsdp: rsdp_ref, root, entry_size })
+    }
+
+    /// Returns the revision of the RSDP; 0 for ACPI 1.0, 2 for newer ones
+    pub fn revision(&self) -> u8 {
+        self.rsdp.revision
+    }
+
+    /// Returns the OEM ID of the RSDP
+    pub fn oem_id(&self) -> [u8; 6] {
+        self.rsdp.oem_id
+    }
+
+    /// Returns the XSDT or RSDT the RSDP points to
+    pub fn root(&self) -> &'static SdtHeader {
+        self.root
+    }
+
+    /// Returns the validated system description tables listed in the XSDT or
+    /// RSDT. Tables failing validation are returned as errors.
+    pub fn tables(&self)
+            -> impl Iterator<Item = Result<&'static SdtHeader, Error>> {
+        let entries = self.root as *const SdtHeader as usize +
+            size_of::<SdtHeader>();
+        let count = (self.root.length as usize - size_of::<SdtHeader>()) /
+            self.entry_size;
+        let entry_size = self.entry_size;
+
+        (0..count).filter_map(move |idx| {
+            // The entries are only 4-byte aligned
+            let entry = (entries + idx * entry_size) as *const u8;
+            let addr = unsafe {
+                if entry_size == 8 {
+                    (entry as *const u64).read_unaligned() as usize
+                } else {
+                    (entry as *const u32).read_unaligned() as usize
+                }
+            };
+
+            // Skip empty entries
+            if addr == 0 { return None; }
+            Some(unsafe { validate_sdt(addr as *const SdtHeader) })
+        })
+    }
+
+    /// Returns the first valid table with `signature`
+    pub fn find_sdt(&self, signature: Signature)
+            -> Result<&'static SdtHeader, Error> {
+        // Remember why the last matching table was rejected, if it was
+        let mut ret = Err(Error::NotFound(signature));
+
+        for table in self.tables() {
+            match table {
+                Ok(table) if table.signature == signature => return Ok(table),
+                Err(err @ (Error::WrongChecksum(sig) |
+                           Error::WrongLength { signature: sig, .. }))
+                        if sig == signature => ret = Err(err),
+                _ => {},
+            }
+        }
+        ret
+    }
+
+    /// Returns the table `T`
+    pub fn find<T: Table>(&self) -> Result<&'static T, Error> {
+        let table = self.find_sdt(T::SIGNATURE)?;
+        unsafe { validate(table) }
+    }
+}
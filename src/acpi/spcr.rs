@@ -0,0 +1,116 @@
+//! Serial Port Console Redirection table
+//!
+//! Tells us which UART the firmware uses as its console, where its registers
+//! are and how it's configured.
+
+use crate::acpi::{ GenericAddress, Signature, SdtHeader, Table };
+
+/// Interface type of a full 16550-compatible UART
+pub const INTERFACE_16550: u8 = 0x00;
+
+/// Interface type of a 16450-compatible UART, a subset of the 16550
+pub const INTERFACE_16450: u8 = 0x01;
+
+/// Interface type of a 16550-compatible UART whose register access is
+/// described by the generic address structure
+pub const INTERFACE_16550_GAS: u8 = 0x12;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+/// The SPCR
+pub struct Spcr {
+    /// The table header
+    pub header: SdtHeader,
+
+    /// Type of the UART, e.g. [`INTERFACE_16550`]
+    pub interface_type: u8,
+
+    /// Reserved
+    reserved: [u8; 3],
+
+    /// Location of the registers of the UART
+    pub base_address: GenericAddress,
+
+    /// Which kinds of interrupts the UART supports
+    pub interrupt_type: u8,
+
+    /// PC-AT IRQ of the UART
+    pub irq: u8,
+
+    /// Global system interrupt of the UART
+    pub gsi: u32,
+
+    /// Encoded baud rate; see [`Spcr::baud_rate()`]
+    pub baud_rate: u8,
+
+    /// 0 for no parity
+    pub parity: u8,
+
+    /// 1 for one stop bit
+    pub stop_bits: u8,
+
+    /// Flow control flags
+    pub flow_control: u8,
+
+    /// Terminal protocol the firmware uses on the console
+    pub terminal_type: u8,
+
+    /// Language the firmware uses on the console
+    pub language: u8,
+
+    /// Device ID of a PCI UART, 0xFFFF if it isn't one
+    pub pci_device_id: u16,
+
+    /// Vendor ID of a PCI UART, 0xFFFF if it isn't one
+    pub pci_vendor_id: u16,
+
+    /// Bus number of a PCI UART
+    pub pci_bus: u8,
+
+    /// Device number of a PCI UART
+    pub pci_device: u8,
+
+    /// Function number of a PCI UART
+    pub pci_function: u8,
+
+    /// PCI flags
+    pub pci_flags: u32,
+
+    /// Segment number of a PCI UART
+    pub pci_segment: u8,
+
+    /// Frequency of the input clock of the UART in Hz; revision 3 and newer
+    /// only, reserved before
+    pub uart_clock_frequency: u32,
+}
+
+unsafe impl Table for Spcr {
+    const SIGNATURE: Signature = Signature(*b"SPCR");
+}
+
+impl Spcr {
+    /// Returns whether the UART is 16550-compatible
+    pub fn is_16550(&self) -> bool {
+        matches!(self.interface_type,
+                 INTERFACE_16550 | INTERFACE_16450 | INTERFACE_16550_GAS)
+    }
+
+    /// Returns the baud rate the UART is configured for, or `None` if the
+    /// firmware left it as it is
+    pub fn baud_rate(&self) -> Option<u32> {
+        match self.baud_rate {
+            3 => Some(9600),
+            4 => Some(19200),
+            6 => Some(57600),
+            7 => Some(115200),
+            _ => None,
+        }
+    }
+
+    /// Returns the frequency of the input clock of the UART in Hz, if the
+    /// table tells
+    pub fn clock_frequency(&self) -> Option<u32> {
+        let freq = self.uart_clock_frequency;
+        (self.header.revision >= 3 && freq != 0).then_some(freq)
+    }
+}
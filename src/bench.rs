@@ -0,0 +1,323 @@
+//! Benchmark harness
+//!
+//! Runs a closure a number of times, timing each run with serialized TSC reads
+//! and reducing the samples into a handful of statistics that are robust
+//! enough to compare algorithms with.
+//!
+//! Benchmarks can be registered from any module (or any crate linked into the
+//! kernel) with [`benchmark!`]. Registered benchmarks are placed into the
+//! `.bench` link section and are enumerated at runtime by [`run_all()`], so
+//! adding an experiment doesn't require touching `main.rs`.
+
+use core::fmt;
+use core::hint::black_box;
+use alloc::vec::Vec;
+use crate::{ cpu, efi, rng, results };
+use crate::cmdline::CMDLINE;
+
+/// Number of runs used to estimate the overhead of the timing code itself
+const OVERHEAD_RUNS: usize = 256;
+
+/// A benchmark registered with [`benchmark!`]
+#[derive(Debug)]
+#[repr(C)]
+pub struct Benchmark {
+    /// Name of the benchmark, used in the report and for filtering
+    pub name: &'static str,
+
+    /// Configuration the benchmark is run with
+    pub bench: Bench,
+
+    /// The benchmark itself. It's expected to prepare its inputs and call
+    /// [`Bench::run()`] on the configuration it receives
+    pub func: fn(&Bench) -> Stats,
+}
+
+// The registered benchmarks live in `.bench$b`. PE linkers sort grouped
+// sections by the suffix after the `$`, so these two markers delimit them.
+
+/// Start of the registered benchmarks
+#[used]
+#[unsafe(link_section = ".bench$a")]
+static BENCHMARKS_START: [Benchmark; 0] = [];
+
+/// End of the registered benchmarks
+#[used]
+#[unsafe(link_section = ".bench$c")]
+static BENCHMARKS_END: [Benchmark; 0] = [];
+
+/// Returns all benchmarks registered with [`benchmark!`]
+pub fn benchmarks() -> &'static [Benchmark] {
+    let start = BENCHMARKS_START.as_ptr();
+    let end = BENCHMARKS_END.as_ptr();
+    let len = (end as usize - start as usize) / size_of::<Benchmark>();
+    unsafe { core::slice::from_raw_parts(start, len) }
+}
+
+/// Run all registered benchmarks and report their statistics.
+///
+/// If `filter` is given, only benchmarks whose name contains it are run.
+/// `iters=` and `warmup=` on the kernel command line override the
+/// configuration of every benchmark. Every benchmark gets a fresh seed for its
+/// random inputs unless one is given with `seed=`, which replays a run.
+/// With `persist`, the results are saved across boots and compared to the
+/// earlier ones; `persist=reset` starts over.
+/// Returns the number of benchmarks that were run.
+pub fn run_all(filter: Option<&str>) -> usize {
+    // Get the overrides from the command line
+    let (iters, warmup, seed, persist) = {
+        let cmdline = CMDLINE.lock();
+        (cmdline.get_usize("iters"), cmdline.get_usize("warmup"),
+         cmdline.get_usize("seed"),
+         cmdline.get("persist").map(|persist| persist == "reset"))
+    };
+
+    // Timestamp the results if the firmware knows what time it is
+    if let Some(Ok(time)) = efi::runtime_services().map(|rt| rt.get_time()) {
+        print!("Running benchmarks at {}\n", time);
+    }
+
+    let mut ran = 0;
+    for bench in benchmarks() {
+        // Skip the benchmarks we weren't asked for
+        if filter.is_some_and(|filter| !bench.name.contains(filter)) {
+            continue;
+        }
+
+        // Apply the overrides
+        let mut config = bench.bench;
+        if let Some(iters)  = iters  { config = config.iters(iters);   }
+        if let Some(warmup) = warmup { config = config.warmup(warmup); }
+
+        // Seed the random inputs
+        let seed = seed.map(|seed| seed as u64).unwrap_or_else(rng::seed);
+        config = config.seed(seed);
+
+        let stats = (bench.func)(&config);
+        stats.report(bench.name);
+        ran += 1;
+
+        // Persist the results and compare them to the earlier boots
+        if let Some(reset) = persist {
+            if reset && let Err(err) = results::clear(bench.name) {
+                print!("Couldn't reset {}: {:?}\n", bench.name, err);
+            }
+            match results::record(bench.name, &stats) {
+                Ok(history) => { print!("{:<32} {}\n", "", history); },
+                Err(err) => {
+                    print!("Couldn't persist {}: {:?}\n", bench.name, err);
+                },
+            }
+        }
+    }
+    ran
+}
+
+/// Register a benchmark to be run by [`run_all()`].
+///
+/// The first argument is the name of the benchmark, optionally followed by the
+/// [`Bench`] configuration to use and then a function which receives the
+/// configuration and returns the [`Stats`] of its run:
+///
+/// ```ignore
+/// benchmark!(sum_4k, Bench::new().iters(100), |bench| {
+///     let data = [1u64; 4096];
+///     bench.run(|| data.iter().sum::<u64>())
+/// });
+/// ```
+#[macro_export] macro_rules! benchmark {
+    ($name:ident, $func:expr) => {
+        $crate::benchmark!($name, $crate::bench::Bench::new(), $func);
+    };
+    ($name:ident, $bench:expr, $func:expr) => {
+        const _: () = {
+            #[used]
+            #[unsafe(link_section = ".bench$b")]
+            static BENCHMARK: $crate::bench::Benchmark =
+                $crate::bench::Benchmark {
+                    name:  stringify!($name),
+                    bench: $bench,
+                    func:  $func,
+                };
+        };
+    };
+}
+
+/// Configuration of a single benchmark run
+#[derive(Debug, Clone, Copy)]
+pub struct Bench {
+    /// Number of untimed runs done before measuring, used to warm up the
+    /// caches and the branch predictor
+    warmup: usize,
+
+    /// Number of timed runs
+    iters: usize,
+
+    /// Seed for the random inputs of the benchmark, see [`Bench::rng()`]
+    seed: u64,
+}
+
+impl Bench {
+    /// Returns a new benchmark configuration with sane defaults
+    pub const fn new() -> Self {
+        Self {
+            warmup: 16,
+            iters:  1000,
+            seed:   0,
+        }
+    }
+
+    /// Set the number of untimed warmup runs
+    pub const fn warmup(mut self, warmup: usize) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Set the number of timed runs. At least one run is always done.
+    pub const fn iters(mut self, iters: usize) -> Self {
+        self.iters = if iters == 0 { 1 } else { iters };
+        self
+    }
+
+    /// Set the seed of the random inputs
+    pub const fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Returns a PRNG for generating the inputs of the benchmark. It produces
+    /// the same inputs every time it's created from the same configuration.
+    pub fn rng(&self) -> rng::Xoshiro256StarStar {
+        rng::Xoshiro256StarStar::new(self.seed)
+    }
+
+    /// Run `func` `warmup` times without timing it, then `iters` times while
+    /// timing each run, and return the statistics of the timed runs.
+    ///
+    /// The value returned by `func` is passed through [`black_box`] so the
+    /// compiler can't optimize the work away.
+    pub fn run<R, F: FnMut() -> R>(&self, mut func: F) -> Stats {
+        // Warm up
+        for _ in 0..self.warmup {
+            black_box(func());
+        }
+
+        // Estimate how many cycles the timing itself costs
+        let overhead = Self::overhead();
+
+        // Time each run separately
+        let mut samples = Vec::with_capacity(self.iters);
+        for _ in 0..self.iters {
+            let start = cpu::tsc_start();
+            black_box(func());
+            let end = cpu::tsc_end();
+
+            let cycles = end.wrapping_sub(start).saturating_sub(overhead);
+            samples.push(cycles as u64);
+        }
+
+        Stats { seed: self.seed, ..Stats::new(&mut samples) }
+    }
+
+    /// Returns the minimal number of cycles measured for an empty region
+    fn overhead() -> usize {
+        (0..OVERHEAD_RUNS).map(|_| {
+            let start = cpu::tsc_start();
+            let end = cpu::tsc_end();
+            end.wrapping_sub(start)
+        }).min().unwrap_or(0)
+    }
+}
+
+impl Default for Bench {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Statistics of a benchmark run. All values are in TSC cycles.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    /// Number of samples the statistics were computed from
+    pub iters: usize,
+
+    /// Fastest run
+    pub min: u64,
+
+    /// Slowest run
+    pub max: u64,
+
+    /// Median run
+    pub median: u64,
+
+    /// Arithmetic mean of all runs
+    pub mean: u64,
+
+    /// Population standard deviation of all runs
+    pub stddev: u64,
+
+    /// 99th percentile (nearest-rank)
+    pub p99: u64,
+
+    /// Seed the inputs were generated from; `seed=` replays the run
+    pub seed: u64,
+}
+
+impl Stats {
+    /// Compute the statistics from `samples`. The samples get sorted and the
+    /// seed is left at 0.
+    ///
+    /// Panics if `samples` is empty.
+    pub fn new(samples: &mut [u64]) -> Self {
+        assert!(!samples.is_empty(), "Can't compute statistics of 0 samples");
+        samples.sort_unstable();
+
+        let n = samples.len();
+
+        // Median; the average of the two middle samples for even counts
+        let median = if n % 2 == 1 {
+            samples[n / 2]
+        } else {
+            ((samples[n / 2 - 1] as u128 + samples[n / 2] as u128) / 2) as u64
+        };
+
+        // Mean
+        let sum = samples.iter().map(|&x| x as u128).sum::<u128>();
+        let mean = (sum / n as u128) as u64;
+
+        // Standard deviation, computed in integers. Sub-cycle precision is
+        // meaningless anyway
+        let var = samples.iter().map(|&x| {
+            let diff = x.abs_diff(mean) as u128;
+            diff * diff
+        }).sum::<u128>() / n as u128;
+
+        // Nearest-rank 99th percentile
+        let p99 = samples[(n * 99).div_ceil(100) - 1];
+
+        Self {
+            iters: n,
+            min: samples[0],
+            max: samples[n - 1],
+            median,
+            mean,
+            stddev: var.isqrt() as u64,
+            p99,
+            seed: 0,
+        }
+    }
+
+    /// Print the statistics over the serial line, prefixed by `name`
+    pub fn report(&self, name: &str) {
+        print!("{:<32} {}\n", name, self);
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "min {:>10} | median {:>10} | mean {:>10} | \
+                   stddev {:>10} | p99 {:>10} cycles ({} iters, seed {:#x})",
+               self.min, self.median, self.mean, self.stddev, self.p99,
+               self.iters, self.seed)
+    }
+}
@@ -0,0 +1,435 @@
+//! Boot flow shared by every kernel built on top of this crate
+//!
+//! Downstream crates don't have to write their own `efi_main`; they call
+//! [`entry!`] with their entry point and get a fully initialized system.
+
+use core::mem::size_of;
+use core::sync::atomic::{ AtomicUsize, Ordering };
+use crate::{ efi, serial, mm, cpu, files, rng, smp, acpi, power,
+             qemu, hpet };
+use crate::fbcon::FbCon;
+use crate::cmdline::{ self, Cmdline };
+use crate::rangeset::{ Range, RangeSet };
+
+/// Size of the kernel stack in bytes, including the guard region
+pub const STACK_SIZE: usize = 1024 * 1024;
+
+/// Size of the guard region at the bottom of the kernel stack in bytes
+pub const STACK_GUARD_SIZE: usize = 16 * 1024;
+
+/// Pattern the stack guard region is filled with
+const STACK_GUARD_PATTERN: u64 = 0xDEAD_57AC_DEAD_57AC;
+
+/// Number of nanoseconds the TSC is measured against the HPET at boot
+const TSC_CALIBRATION_NANOS: u64 = 10_000_000;
+
+/// Number of nanoseconds the HPET one-shot checked at boot is armed for
+const ONESHOT_CHECK_NANOS: u64 = 1_000_000;
+
+/// Address of the bottom of the kernel stack, i.e. of its guard region
+static STACK_BOTTOM: AtomicUsize = AtomicUsize::new(0);
+
+/// Information about the booted system handed to the kernel entry point
+#[derive(Debug)]
+pub struct BootInfo {
+    /// Handle of the loaded kernel image
+    pub img_handle: efi::Handle,
+
+    /// The EFI system table. The boot services are no longer available by the
+    /// time the kernel receives this.
+    pub sys_table: *mut efi::SystemTable,
+
+    /// Inclusive range of the kernel stack, including the guard region
+    pub stack: Range,
+
+    /// Inclusive range of memory our image was loaded to
+    pub image: Range,
+
+    /// The kernel command line, parsed from the load options of our image
+    pub cmdline: Cmdline,
+
+    /// The framebuffer of the selected graphics mode, if there is one
+    pub framebuffer: Option<efi::gop::Framebuffer>,
+}
+
+/// Arguments carried over from `efi_main` to the kernel stack
+struct BootArgs {
+    /// Handle of the loaded kernel image
+    img_handle: efi::Handle,
+
+    /// The EFI system table
+    sys_table: *mut efi::SystemTable,
+
+    /// The kernel entry point
+    main: fn(&BootInfo),
+
+    /// Inclusive range of memory our image was loaded to
+    image: Range,
+
+    /// The kernel command line
+    cmdline: Cmdline,
+
+    /// The framebuffer of the selected graphics mode
+    framebuffer: Option<efi::gop::Framebuffer>,
+}
+
+/// Boot the system and call `main` with the [`BootInfo`].
+///
+/// The firmware runs us on a stack in `BootServicesData` memory, which is
+/// handed to the memory manager once the boot services are exited. Before
+/// exiting them, we switch to a stack of our own and continue there.
+///
+/// # Safety
+///
+/// This must be called exactly once, with the arguments `efi_main` received.
+pub unsafe fn boot(img_handle: efi::Handle, sys_table: *mut efi::SystemTable,
+                   main: fn(&BootInfo)) -> ! {
+    // Find out whether the host wants to hear how the run went before
+    // anything can panic. The command line gets the last word later on
+    qemu::probe();
+
+    // Make sure the firmware handed us sane tables before we follow any of
+    // their pointers. If they aren't, the panic goes out over the serial
+    // ports, as ConOut can't be trusted
+    let revision = unsafe { efi::table::validate_system_table(sys_table) }
+        .unwrap_or_else(|err| panic!("Invalid EFI system table: {:?}", err));
+
+    // Keep the system table around for the runtime services and the console,
+    // which prints through the firmware until the boot services are exited
+    unsafe { efi::register_system_table(sys_table) };
+
+    let vendor = unsafe { efi::ucs2::from_ptr((*sys_table).fw_vendor) };
+    print!("UEFI {} firmware by {}\n", revision, efi::ucs2::Display(vendor));
+
+    // List what the ACPI tables have to offer
+    match acpi::Acpi::new() {
+        Ok(acpi) => {
+            print!("ACPI tables:");
+            for table in acpi.tables() {
+                match table {
+                    Ok(table) => { print!(" {}", table.signature); },
+                    Err(err) => { print!(" {:?}", err); },
+                }
+            }
+            print!("\n");
+        },
+        Err(err) => { print!("No ACPI tables: {:?}\n", err); },
+    }
+
+    let boot_svc = unsafe { &*((*sys_table).boot_svc) };
+
+    // Find out where our image lives and what arguments it was started with.
+    // The load options live in pool memory, so they have to be copied out
+    // before the boot services are exited
+    let loaded_image = unsafe {
+        &*boot_svc.handle_protocol::<efi::LoadedImage>(img_handle)
+            .expect("Couldn't get the loaded image protocol.")
+    };
+    let image_base = loaded_image.image_base as usize;
+    let image_size = loaded_image.image_size as usize;
+    let image = image_size.checked_sub(1)
+        .and_then(|last| image_base.checked_add(last))
+        .and_then(|end| Range::new(image_base, end).ok())
+        .unwrap_or_else(|| panic!("Invalid image range: {:#x} bytes at {:#x}",
+                                  image_size, image_base));
+    let cmdline = Cmdline::from_ucs2(loaded_image.load_options());
+
+    // Let the command line move or disable the debug exit device
+    if let Some(port) = qemu::init(&cmdline) {
+        print!("QEMU debug exit at port {:#x}\n", port);
+    }
+
+    // Load the files requested with `files=` from our boot volume and the ones
+    // requested with `tftp=` from the boot server
+    load_files(cmdline.get("files"), |path| unsafe {
+        efi::fs::load_file(boot_svc, img_handle, path)
+    });
+    load_files(cmdline.get("tftp"), |name| unsafe {
+        efi::pxe::download(boot_svc, img_handle, name)
+    });
+
+    // Pick a graphics mode, either the one requested with `gop=WIDTHxHEIGHT`
+    // or whatever the firmware has set up
+    let resolution = cmdline.get("gop").and_then(|gop| {
+        let (width, height) = gop.split_once('x')?;
+        Some((width.parse().ok()?, height.parse().ok()?))
+    });
+    let framebuffer = match efi::gop::select_mode(boot_svc, resolution) {
+        Ok(fb) => {
+            print!("Framebuffer {}x{} at {:#x}\n",
+                   fb.width, fb.height, fb.base);
+            Some(fb)
+        },
+        Err(err) => {
+            print!("No framebuffer console: {:?}\n", err);
+            None
+        },
+    };
+
+    // Save some entropy from the firmware in case the CPU has none to offer
+    if !rng::collect_firmware_entropy() {
+        print!("No entropy from the firmware\n");
+    }
+
+    // Park the APs in our own loop while the firmware can still start them,
+    // unless we're asked to stay on the BSP
+    if !cmdline.contains("nosmp") {
+        match smp::start_aps(boot_svc) {
+            Ok(parked) => { print!("Parked {} APs\n", parked); },
+            Err(err) => { print!("Couldn't start the APs: {:?}\n", err); },
+        }
+    }
+
+    // Allocate the kernel stack. It's `LoaderData`, so the memory manager
+    // never gets to hand it out
+    let stack = boot_svc.allocate_pages(efi::AllocateType::AnyPages,
+                                        efi::MemoryType::LoaderData,
+                                        STACK_SIZE / 4096, 0)
+        .expect("Couldn't allocate the kernel stack.") as usize;
+
+    // Fill the guard region so overflows can be detected
+    let guard = stack as *mut u64;
+    for idx in 0..STACK_GUARD_SIZE / size_of::<u64>() {
+        unsafe { guard.add(idx).write_volatile(STACK_GUARD_PATTERN) };
+    }
+    STACK_BOTTOM.store(stack, Ordering::SeqCst);
+
+    // Place the arguments at the top of the new stack and continue there
+    let top = stack + STACK_SIZE;
+    let args = (top - size_of::<BootArgs>()) & !0xF;
+    unsafe {
+        (args as *mut BootArgs).write(BootArgs {
+            img_handle, sys_table, main, image, cmdline, framebuffer
+        });
+        cpu::switch_stack(args, boot_continue, args);
+    }
+}
+
+/// Load every file in the comma-separated `names` with `load` and register it
+/// with the [`files`] registry under its name
+fn load_files<E: core::fmt::Debug>(
+    names: Option<&str>,
+    load: impl Fn(&str) -> Result<&'static [u8], E>
+) {
+    for name in names.unwrap_or("").split(',') {
+        if name.is_empty() { continue; }
+
+        match load(name) {
+            Ok(data) => {
+                files::register(name, data)
+                    .unwrap_or_else(|err| panic!("Couldn't register {}: {:?}",
+                                                 name, err));
+                print!("Loaded {} ({} bytes)\n", name, data.len());
+            },
+            Err(err) => { print!("Couldn't load {}: {:?}\n", name, err); },
+        }
+    }
+}
+
+/// Second half of [`boot()`], running on the kernel stack
+extern "sysv64" fn boot_continue(args: usize) -> ! {
+    let BootArgs { img_handle, sys_table, main, image, cmdline, framebuffer } =
+        unsafe { (args as *const BootArgs).read() };
+
+    // Get the free memory map and exit the boot services.
+    let memory = unsafe { efi::memory_map_exit(img_handle, sys_table) };
+
+    // The UARTs are ours now, so the console switches over to them. Even if
+    // exiting failed, the firmware console is off limits and we need some way
+    // to report that
+    serial::Serial::init();
+    let mut memory = memory.expect("Couldn't acquire the free memory map.");
+
+    // Don't let the allocator hand out structures we still use
+    reserve_firmware_structures(&mut memory, unsafe { &*sys_table });
+
+    // Initialize the memory manager
+    // UEFI automatically sets up 1:1 paging, so each access is direct to
+    // physical memory.
+    mm::init(memory);
+
+    // The firmware is done drawing to the screen, so it's ours now
+    if let Some(fb) = framebuffer { FbCon::init(fb); }
+
+    // Some firmware takes the APs back while exiting the boot services
+    let (_, lost) = smp::check_parked();
+    if lost > 0 {
+        print!("{} APs were taken back by the firmware\n", lost);
+    }
+
+    // Start the reference clock and measure the TSC against it
+    match hpet::init() {
+        Ok(hpet) => {
+            print!("HPET: {} timers, {} fs period\n",
+                   hpet.timers(), hpet.period());
+
+            match hpet.calibrate_tsc(TSC_CALIBRATION_NANOS) {
+                Ok(tsc) => {
+                    let invariant =
+                        if cpu::has_invariant_tsc() { "" } else { " not" };
+                    print!("TSC: {} kHz,{} invariant\n", tsc / 1000, invariant);
+                },
+                Err(err) => {
+                    print!("Couldn't calibrate the TSC: {:?}\n", err);
+                },
+            }
+
+            // Make sure a polled one-shot fires
+            match hpet.check_oneshot(0, ONESHOT_CHECK_NANOS) {
+                Ok(nanos) => {
+                    print!("HPET one-shot fired after {} ns\n", nanos);
+                },
+                Err(err) => { print!("HPET one-shot failed: {:?}\n", err); },
+            }
+        },
+        Err(err) => { print!("No HPET: {:?}\n", err); },
+    }
+
+    // Summarize the interrupt controllers for whoever sets them up
+    if let Ok(madt) = acpi::Acpi::new()
+            .and_then(|acpi| acpi.find::<acpi::madt::Madt>()) {
+        let topology = madt.topology();
+        print!("MADT: {} of {} CPUs enabled, {} IO APICs, {} overrides\n",
+               topology.enabled_processors().count(),
+               topology.processors.len(), topology.io_apics.len(),
+               topology.overrides.len());
+    }
+
+    // Make the command line available to everyone
+    *cmdline::CMDLINE.lock() = cmdline;
+
+    let stack = STACK_BOTTOM.load(Ordering::SeqCst);
+    let info = BootInfo {
+        img_handle,
+        sys_table,
+        stack: Range::new(stack, stack + STACK_SIZE - 1).unwrap(),
+        image,
+        cmdline,
+        framebuffer,
+    };
+
+    main(&info);
+    finish()
+}
+
+/// Remove the `size` bytes at `addr` from `memory`, rounded out to pages
+fn reserve(memory: &mut RangeSet, addr: usize, size: usize) {
+    if addr == 0 || size == 0 { return; }
+
+    let start = addr & !0xFFF;
+    let end = addr.saturating_add(size - 1) | 0xFFF;
+    memory.remove(Range::new(start, end).unwrap())
+        .expect("Couldn't reserve a firmware structure.");
+}
+
+/// Remove the firmware structures we keep referencing after the boot services
+/// are exited from `memory`. They normally live in runtime memory, but not all
+/// firmware gets that right.
+fn reserve_firmware_structures(memory: &mut RangeSet,
+                               sys_table: &efi::SystemTable) {
+    // The system table itself and the firmware vendor string
+    reserve(memory, sys_table as *const _ as usize,
+            size_of::<efi::SystemTable>());
+    reserve(memory, sys_table.fw_vendor as usize, 4096);
+
+    // The runtime services table
+    reserve(memory, sys_table.runtime_svc as usize,
+            size_of::<efi::RuntimeServices>());
+
+    // The configuration table array and the tables in it
+    reserve(memory, sys_table.cfg_tables as usize,
+            sys_table.n_cfg_entries * size_of::<efi::ConfigTable>());
+    for cfg in sys_table.config_tables() {
+        reserve_config_table(memory, cfg);
+    }
+}
+
+/// Remove the configuration table `cfg` from `memory`. The ACPI tables are
+/// removed in full once their checksums check out, any other table only
+/// with its first page, as its length can't be trusted.
+fn reserve_config_table(memory: &mut RangeSet, cfg: &efi::ConfigTable) {
+    let table = cfg.table as usize;
+    if table == 0 { return; }
+
+    match cfg.guid {
+        efi::guid::ACPI_20_TABLE | efi::guid::ACPI_TABLE => {
+            let rsdp = table as *const acpi::Rsdp;
+            let Ok(acpi) = (unsafe { acpi::Acpi::from_rsdp(rsdp) }) else {
+                reserve(memory, table, 1);
+                return;
+            };
+
+            // The RSDP, whose length is only there since ACPI 2.0
+            let length = if acpi.revision() >= 2 {
+                unsafe { (*rsdp).length as usize }
+            } else {
+                size_of::<acpi::Rsdp>()
+            };
+            reserve(memory, table, length);
+
+            // The XSDT or RSDT, every table it lists and the DSDT, which
+            // only the FADT points to
+            let root = acpi.root();
+            reserve(memory, root as *const _ as usize, root.length as usize);
+            for sdt in acpi.tables().flatten() {
+                reserve(memory, sdt as *const _ as usize, sdt.length as usize);
+            }
+            if let Ok(dsdt) = acpi.find::<acpi::fadt::Fadt>()
+                    .and_then(|fadt| fadt.dsdt()) {
+                reserve(memory, dsdt as *const _ as usize,
+                        dsdt.header.length as usize);
+            }
+        },
+        _ => reserve(memory, table, 1),
+    }
+}
+
+/// Check whether the guard region of the kernel stack is intact.
+///
+/// There's no paging set up by us, so the guard region isn't protected; an
+/// overflow is only detected after the fact, when this is called.
+pub fn stack_guard_intact() -> bool {
+    let bottom = STACK_BOTTOM.load(Ordering::SeqCst);
+    if bottom == 0 { return true; }
+
+    (0..STACK_GUARD_SIZE / size_of::<u64>()).all(|idx| unsafe {
+        (bottom as *const u64).add(idx).read_volatile() == STACK_GUARD_PATTERN
+    })
+}
+
+/// Called once the kernel entry point returns. Powers the system off through
+/// ACPI or the runtime services, or halts if neither works.
+pub fn finish() -> ! {
+    // Make sure we haven't been running over someone else's memory
+    assert!(stack_guard_intact(), "The kernel stack has overflowed.");
+
+    print!("Reached end of execution.\n");
+
+    // Tell the host we're done, if it's listening, before powering off
+    qemu::exit(qemu::ExitCode::Success);
+    power::shutdown()
+}
+
+/// Generate the `efi_main` entry point, which boots the system through
+/// [`boot()`] and then calls `$main` with the [`BootInfo`].
+///
+/// `$main` must be a `fn(&BootInfo)`:
+///
+/// ```ignore
+/// kernel::entry!(main);
+///
+/// fn main(boot: &kernel::boot::BootInfo) {
+///     // Your code here :)
+/// }
+/// ```
+#[macro_export] macro_rules! entry {
+    ($main:path) => {
+        #[unsafe(no_mangle)]
+        extern "efiapi" fn efi_main(img_handle: $crate::efi::Handle,
+                                    sys_table: *mut $crate::efi::SystemTable)
+                -> $crate::efi::RawStatus {
+            // Boot the system and hand it over to the kernel
+            unsafe { $crate::boot::boot(img_handle, sys_table, $main) }
+        }
+    };
+}
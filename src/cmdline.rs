@@ -0,0 +1,98 @@
+//! Kernel command line
+//!
+//! The command line is taken from the load options of our image, so it can be
+//! passed from the UEFI shell (`kernel.efi bench=sort iters=100`) or a boot
+//! entry. It's a whitespace separated list of `key=value` pairs and flags.
+
+use crate::spinlock::SpinLock;
+
+/// Maximum length of the command line in bytes. Anything longer is truncated.
+pub const CMDLINE_MAX: usize = 1024;
+
+/// The kernel command line, for code which doesn't have access to the
+/// [`BootInfo`](crate::boot::BootInfo). Set during boot.
+pub static CMDLINE: SpinLock<Cmdline> = SpinLock::new(Cmdline::empty());
+
+/// A kernel command line
+#[derive(Clone, Copy)]
+pub struct Cmdline {
+    /// UTF-8 encoded command line
+    buf: [u8; CMDLINE_MAX],
+
+    /// Number of bytes used in `buf`
+    len: usize,
+}
+
+impl Cmdline {
+    /// Returns an empty command line
+    pub const fn empty() -> Self {
+        Self { buf: [0; CMDLINE_MAX], len: 0 }
+    }
+
+    /// Create a command line from a UCS-2 string.
+    ///
+    /// Characters that can't be decoded are replaced and the command line is
+    /// truncated to [`CMDLINE_MAX`] bytes.
+    pub fn from_ucs2(ucs2: &[u16]) -> Self {
+        let mut cmdline = Self::empty();
+        for c in char::decode_utf16(ucs2.iter().copied()) {
+            let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+
+            // Stop once the character doesn't fit anymore
+            if cmdline.len + c.len_utf8() > CMDLINE_MAX { break; }
+            cmdline.len += c.encode_utf8(&mut cmdline.buf[cmdline.len..]).len();
+        }
+        cmdline
+    }
+
+    /// Returns the whole command line
+    pub fn as_str(&self) -> &str {
+        // Only ever filled with whole encoded characters
+        core::str::from_utf8(&self.buf[..self.len]).unwrap()
+    }
+
+    /// Returns an iterator over the `(key, value)` pairs of the command line.
+    /// Flags without a value have no value.
+    pub fn args(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.as_str().split_whitespace().map(|arg| {
+            match arg.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None               => (arg, None),
+            }
+        })
+    }
+
+    /// Returns the value of the last `key` on the command line. Flags have an
+    /// empty value.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.args()
+            .filter(|&(k, _)| k == key)
+            .map(|(_, value)| value.unwrap_or(""))
+            .last()
+    }
+
+    /// Returns the value of `key` parsed as a number. Hexadecimal numbers are
+    /// prefixed with `0x`.
+    pub fn get_usize(&self, key: &str) -> Option<usize> {
+        parse_usize(self.get(key)?)
+    }
+
+    /// Returns whether `key` is on the command line
+    pub fn contains(&self, key: &str) -> bool {
+        self.args().any(|(k, _)| k == key)
+    }
+}
+
+/// Parse `value` as a number. Hexadecimal numbers are prefixed with `0x`.
+pub fn parse_usize(value: &str) -> Option<usize> {
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None      => value.parse().ok(),
+    }
+}
+
+impl core::fmt::Debug for Cmdline {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
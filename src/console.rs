@@ -0,0 +1,87 @@
+//! The kernel console
+//!
+//! While the boot services are around, the firmware owns the hardware, so
+//! `print!()` goes through its ConOut device. Once they're exited, everything
+//! is written to every initialized output device instead: the serial ports
+//! and, if there's a framebuffer, the framebuffer console.
+
+use core::fmt::Write;
+use crate::efi;
+use crate::serial::SERIAL_DRIVER;
+use crate::fbcon::FRAMEBUFFER_CONSOLE;
+use crate::spinlock::SpinLock;
+
+/// Serializes the calls into ConOut, which isn't reentrant
+static BOOT_CONSOLE_LOCK: SpinLock<()> = SpinLock::new(());
+
+/// Returns the firmware's ConOut device if the boot services haven't been
+/// exited yet. It's only ever shared, as a panic can print while `print!()`
+/// is still using it.
+fn boot_console() -> Option<&'static efi::SimpleTextOutput> {
+    if efi::boot_services_exited() { return None; }
+
+    efi::system_table()
+        .and_then(|sys_table| unsafe { sys_table.con_out.as_ref() })
+}
+
+/// A dummy struct that implements `Write` such that `print!()` can be used on
+/// it
+pub struct Console;
+
+impl Write for Console {
+    fn write_str(&mut self, string: &str) -> core::fmt::Result {
+        if let Some(con_out) = boot_console() {
+            let _lock = BOOT_CONSOLE_LOCK.lock();
+            let _ = con_out.output(string);
+            return Ok(());
+        }
+
+        if let Some(serial) = &mut *SERIAL_DRIVER.lock() {
+            serial.write(string.as_bytes());
+        }
+        if let Some(fbcon) = &mut *FRAMEBUFFER_CONSOLE.lock() {
+            fbcon.write(string.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// Console `print!()` support
+#[macro_export] macro_rules! print {
+    ($($arg:tt)*) => {
+        let _ = <$crate::console::Console as core::fmt::Write>::write_fmt(
+            &mut $crate::console::Console, format_args!($($arg)*));
+    }
+}
+
+/// Dummy type to implement the `Write` trait on -- used for console shattering
+pub struct ConsoleShatter;
+
+impl Write for ConsoleShatter {
+    fn write_str(&mut self, string: &str) -> core::fmt::Result {
+        if let Some(con_out) = boot_console() {
+            let _ = con_out.output(string);
+            return Ok(());
+        }
+
+        unsafe {
+            if let Some(serial) = &mut *SERIAL_DRIVER.shatter() {
+                serial.write(string.as_bytes());
+            }
+            if let Some(fbcon) = &mut *FRAMEBUFFER_CONSOLE.shatter() {
+                fbcon.write(string.as_bytes());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Console `print!()` that shatters the output device locks on print and as
+/// such is unsafe. Meant to be used in panics
+#[macro_export] macro_rules! print_shatter {
+    ($($arg:tt)*) => {
+        let _ = <$crate::console::ConsoleShatter as core::fmt::Write>
+            ::write_fmt(&mut $crate::console::ConsoleShatter,
+                        format_args!($($arg)*));
+    }
+}
@@ -1,11 +1,15 @@
 //! Arch specific routines that interface with the CPU directly
 
 use core::arch::asm;
-use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{ AtomicU8, Ordering };
+use core::arch::x86_64::{ _rdtsc, __rdtscp, _mm_lfence, __cpuid_count };
 
 /// SMR for the active GS base
 pub const IA32_GS_BASE: u32 = 0xC0000101;
 
+/// Number of times RDRAND and RDSEED are retried when they run out of entropy
+const RDRAND_RETRIES: usize = 16;
+
 /// Write a byte to I/O port `addr
 #[inline]
 pub unsafe fn out8(addr: *const u16, byte: u8) {
@@ -20,6 +24,28 @@ pub unsafe fn in8(addr: *const u16) -> u8 {
     byte
 }
 
+/// Output a 16-bit `val` to I/O port `addr`
+///
+/// # Safety
+///
+/// The write has whatever effect the device at `addr` gives it.
+#[inline]
+pub unsafe fn out16(addr: *const u16, val: u16) {
+    unsafe { asm!("out dx, ax", in("dx") addr, in("ax") val) };
+}
+
+/// Read a 16-bit value from I/O port `addr`
+///
+/// # Safety
+///
+/// Reading may have side effects on the device at `addr`.
+#[inline]
+pub unsafe fn in16(addr: *const u16) -> u16 {
+    let mut val: u16;
+    unsafe { asm!("in ax, dx", in("dx") addr, out("ax") val) };
+    val
+}
+
 /// Output a 32-bit `val` to I/O port `addr`
 #[inline]
 pub unsafe fn out32(addr: *const u16, byte: u32) {
@@ -57,6 +83,178 @@ pub unsafe fn rdtsc() -> usize {
     unsafe { _rdtsc() as usize }
 }
 
+/// Calls RDTSCP, which waits for all prior instructions to execute before
+/// reading the counter
+///
+/// # Safety
+///
+/// The CPU must support RDTSCP; see [`has_rdtscp()`]. It raises #UD
+/// otherwise.
+#[inline]
+pub unsafe fn rdtscp() -> usize {
+    let mut aux = 0;
+    unsafe { __rdtscp(&mut aux) as usize }
+}
+
+/// Calls LFENCE, which keeps later instructions from executing before all
+/// prior instructions complete locally
+#[inline]
+pub fn lfence() {
+    // LFENCE is part of SSE2, which every x86_64 CPU has
+    unsafe { _mm_lfence() };
+}
+
+/// Read the TSC at the start of a measured region.
+///
+/// The surrounding fences keep the read from being reordered with either the
+/// preceding code or the code being measured.
+#[inline(always)]
+pub fn tsc_start() -> usize {
+    lfence();
+    let tsc = unsafe { rdtsc() };
+    lfence();
+    tsc
+}
+
+/// Read the TSC at the end of a measured region.
+///
+/// RDTSCP waits for the measured code to retire and the trailing fence keeps
+/// subsequent code from starting before the counter is read. CPUs without
+/// RDTSCP, like QEMU's default model, get a fenced RDTSC instead.
+#[inline(always)]
+pub fn tsc_end() -> usize {
+    // 0 until the first call has asked CPUID, then 1 without RDTSCP and 2
+    // with it
+    static RDTSCP: AtomicU8 = AtomicU8::new(0);
+
+    let mut rdtscp_support = RDTSCP.load(Ordering::Relaxed);
+    if rdtscp_support == 0 {
+        rdtscp_support = if has_rdtscp() { 2 } else { 1 };
+        RDTSCP.store(rdtscp_support, Ordering::Relaxed);
+    }
+
+    let tsc = if rdtscp_support == 2 {
+        unsafe { rdtscp() }
+    } else {
+        lfence();
+        unsafe { rdtsc() }
+    };
+    lfence();
+    tsc
+}
+
+/// Calls CPUID with `leaf` and `subleaf`, returning (eax, ebx, ecx, edx)
+#[inline]
+pub fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+    let res = __cpuid_count(leaf, subleaf);
+    (res.eax, res.ebx, res.ecx, res.edx)
+}
+
+/// Returns whether the TSC ticks at a constant rate in every P-, C- and
+/// T-state
+pub fn has_invariant_tsc() -> bool {
+    cpuid(0x8000_0000, 0).0 >= 0x8000_0007 &&
+        cpuid(0x8000_0007, 0).3 & (1 << 8) != 0
+}
+
+/// Returns whether the CPU supports RDTSCP
+pub fn has_rdtscp() -> bool {
+    cpuid(0x8000_0000, 0).0 >= 0x8000_0001 &&
+        cpuid(0x8000_0001, 0).3 & (1 << 27) != 0
+}
+
+/// Returns whether the CPU supports RDRAND
+pub fn has_rdrand() -> bool {
+    cpuid(1, 0).2 & (1 << 30) != 0
+}
+
+/// Returns whether the CPU supports RDSEED
+pub fn has_rdseed() -> bool {
+    cpuid(0, 0).0 >= 7 && cpuid(7, 0).1 & (1 << 18) != 0
+}
+
+/// Calls RDRAND, which returns the output of the CPU's DRBG.
+///
+/// Returns `None` if no random number was available after a few retries.
+///
+/// # Safety
+///
+/// The CPU must support RDRAND; see [`has_rdrand()`]. It raises #UD
+/// otherwise.
+#[inline]
+pub unsafe fn rdrand() -> Option<u64> {
+    for _ in 0..RDRAND_RETRIES {
+        let val: u64;
+        let ok: u8;
+        unsafe {
+            asm!("rdrand {val}", "setc {ok}",
+                 val = out(reg) val, ok = out(reg_byte) ok)
+        };
+        if ok != 0 { return Some(val); }
+    }
+    None
+}
+
+/// Calls RDSEED, which returns the output of the CPU's entropy source.
+///
+/// Returns `None` if no random number was available after a few retries.
+///
+/// # Safety
+///
+/// The CPU must support RDSEED; see [`has_rdseed()`]. It raises #UD
+/// otherwise.
+#[inline]
+pub unsafe fn rdseed() -> Option<u64> {
+    for _ in 0..RDRAND_RETRIES {
+        let val: u64;
+        let ok: u8;
+        unsafe {
+            asm!("rdseed {val}", "setc {ok}",
+                 val = out(reg) val, ok = out(reg_byte) ok)
+        };
+        if ok != 0 { return Some(val); }
+
+        // The entropy source refills slowly, give it a moment
+        core::hint::spin_loop();
+    }
+    None
+}
+
+/// Switch to the stack whose top is at `stack` and call `func(arg)` on it.
+///
+/// # Safety
+///
+/// `stack` must be the 16-byte aligned top of memory that's ours and large
+/// enough for `func`. The current stack is abandoned for good, so nothing
+/// may still reference it.
+#[inline]
+pub unsafe fn switch_stack(stack: usize, func: extern "sysv64" fn(usize) -> !,
+                           arg: usize) -> ! {
+    unsafe {
+        asm!(
+            "mov rsp, {stack}",
+            "xor ebp, ebp",
+            "call {func}",
+            "ud2",
+            stack = in(reg) stack,
+            func = in(reg) func,
+            in("rdi") arg,
+            options(noreturn)
+        )
+    }
+}
+
+/// Clears the interrupt flag
+///
+/// # Safety
+///
+/// Whoever relies on interrupts on this processor, like the firmware's
+/// timers, stops getting them until they're enabled again.
+#[inline]
+pub unsafe fn disable_interrupts() {
+    unsafe { asm!("cli") };
+}
+
 /// Clears interrupts and halts the core
 #[inline]
 pub unsafe fn halt() -> ! {
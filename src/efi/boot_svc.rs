@@ -0,0 +1,590 @@
+//! EFI Boot Services
+//!
+//! These are only available until `exit_boot_services()` is called.
+
+use core::ffi::c_void;
+use core::ops::Deref;
+use crate::efi::*;
+
+/// A physical address as used by the boot services
+pub type PhysAddr = u64;
+
+/// Handle to an event object
+pub type Event = *const usize;
+
+/// Task priority level
+pub type Tpl = usize;
+
+/// Task priority level of normal execution
+pub const TPL_APPLICATION: Tpl = 4;
+
+/// Task priority level of most event notifications
+pub const TPL_CALLBACK: Tpl = 8;
+
+/// Task priority level of notifications that must be handled quickly
+pub const TPL_NOTIFY: Tpl = 16;
+
+/// Task priority level at which interrupts are disabled
+pub const TPL_HIGH_LEVEL: Tpl = 31;
+
+/// The event is a timer event
+pub const EVT_TIMER: u32 = 0x80000000;
+
+/// The event is allocated from runtime memory
+pub const EVT_RUNTIME: u32 = 0x40000000;
+
+/// The notification function is queued whenever the event is being waited on
+pub const EVT_NOTIFY_WAIT: u32 = 0x00000100;
+
+/// The notification function is queued whenever the event is signaled
+pub const EVT_NOTIFY_SIGNAL: u32 = 0x00000200;
+
+/// The event is signaled when `exit_boot_services()` is called
+pub const EVT_SIGNAL_EXIT_BOOT_SERVICES: u32 = 0x00000201;
+
+/// The event is signaled when `set_virtual_address_map()` is called
+pub const EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE: u32 = 0x60000202;
+
+/// Used in the implementation of `handle_protocol()`
+pub const OPEN_PROTOCOL_BY_HANDLE_PROTOCOL: u32 = 0x01;
+
+/// Used by drivers to get a protocol interface from a handle
+pub const OPEN_PROTOCOL_GET_PROTOCOL: u32 = 0x02;
+
+/// Used by drivers to test for the existence of a protocol interface
+pub const OPEN_PROTOCOL_TEST_PROTOCOL: u32 = 0x04;
+
+/// Used by bus drivers to show that a protocol interface is being used by one
+/// of the child controllers of a bus
+pub const OPEN_PROTOCOL_BY_CHILD_CONTROLLER: u32 = 0x08;
+
+/// Used by a driver to gain access to a protocol interface
+pub const OPEN_PROTOCOL_BY_DRIVER: u32 = 0x10;
+
+/// Used to gain exclusive access to a protocol interface
+pub const OPEN_PROTOCOL_EXCLUSIVE: u32 = 0x20;
+
+/// Notification function called when an event is signaled
+pub type EventNotify = unsafe extern "efiapi" fn(event: Event,
+                                                 context: *mut c_void);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u32)]
+/// How `allocate_pages()` picks the pages to allocate
+pub enum AllocateType {
+    /// Any available range of pages
+    AnyPages,
+
+    /// Any available range of pages whose uppermost address is less than or
+    /// equal to the given address
+    MaxAddress,
+
+    /// Pages at exactly the given address
+    Address,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u32)]
+/// Type of time used by `set_timer()`
+pub enum TimerDelay {
+    /// Cancel the timer
+    Cancel,
+
+    /// Fire the timer periodically
+    Periodic,
+
+    /// Fire the timer once
+    Relative,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u32)]
+/// Type of the interface installed by `install_protocol_interface()`
+pub enum InterfaceType {
+    /// Native interface
+    Native,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u32)]
+/// Which handles are returned by `locate_handle()`
+pub enum LocateSearchType {
+    /// All handles in the system
+    AllHandles,
+
+    /// The next handle that is new for the registration
+    ByRegisterNotify,
+
+    /// All handles that support the given protocol
+    ByProtocol,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// Generic header of a device path node
+pub struct DevicePath {
+    /// Type of the node
+    pub ty: u8,
+
+    /// Sub-type of the node
+    pub sub_type: u8,
+
+    /// Length of the node in bytes, including this header
+    pub length: [u8; 2],
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// Entry returned by `open_protocol_information()`
+pub struct OpenProtocolInformationEntry {
+    /// Handle of the agent that opened the protocol
+    pub agent_handle: Handle,
+
+    /// Handle of the controller that opened the protocol
+    pub controller_handle: Handle,
+
+    /// Attributes the protocol was opened with
+    pub attributes: u32,
+
+    /// Number of times the protocol was opened
+    pub open_count: u32,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// The EFI_BOOT_SERVICES table
+pub struct BootServices {
+    /// The table header for this struct
+    pub hdr: TableHeader,
+
+    /// Raises the task priority level
+    pub raise_tpl: unsafe extern "efiapi" fn(new_tpl: Tpl) -> Tpl,
+
+    /// Restores the task priority level
+    pub restore_tpl: unsafe extern "efiapi" fn(old_tpl: Tpl),
+
+    /// Allocates memory pages from the system
+    pub allocate_pages: unsafe extern "efiapi" fn(
+        ty:          AllocateType,
+        memory_type: MemoryType,
+        pages:       usize,
+        memory:      &mut PhysAddr) -> RawStatus,
+
+    /// Frees memory pages
+    pub free_pages: unsafe extern "efiapi" fn(
+        memory: PhysAddr,
+        pages:  usize) -> RawStatus,
+
+    /// Returns the current boot services memory map and memory map key
+    pub get_memory_map: unsafe extern "efiapi" fn(
+        memory_map_size:    &mut usize,
+        memory_map:         *mut u8,
+        map_key:            &mut usize,
+        descriptor_size:    &mut usize,
+        descriptor_version: &mut u32) -> RawStatus,
+
+    /// Allocates pool memory
+    pub allocate_pool: unsafe extern "efiapi" fn(
+        pool_type: MemoryType,
+        size:      usize,
+        buffer:    &mut *mut u8) -> RawStatus,
+
+    /// Returns pool memory to the system
+    pub free_pool: unsafe extern "efiapi" fn(buffer: *mut u8) -> RawStatus,
+
+    /// Creates an event
+    pub create_event: unsafe extern "efiapi" fn(
+        ty:             u32,
+        notify_tpl:     Tpl,
+        notify_func:    Option<EventNotify>,
+        notify_context: *mut c_void,
+        event:          &mut Event) -> RawStatus,
+
+    /// Sets the type of timer and the trigger time for a timer event
+    pub set_timer: unsafe extern "efiapi" fn(
+        event:        Event,
+        ty:           TimerDelay,
+        trigger_time: u64) -> RawStatus,
+
+    /// Stops execution until an event is signaled
+    pub wait_for_event: unsafe extern "efiapi" fn(
+        number_of_events: usize,
+        event:            *const Event,
+        index:            &mut usize) -> RawStatus,
+
+    /// Signals an event
+    pub signal_event: unsafe extern "efiapi" fn(event: Event) -> RawStatus,
+
+    /// Closes an event
+    pub close_event: unsafe extern "efiapi" fn(event: Event) -> RawStatus,
+
+    /// Checks whether an event is in the signaled state
+    pub check_event: unsafe extern "efiapi" fn(event: Event) -> RawStatus,
+
+    /// Installs a protocol interface on a device handle
+    pub install_protocol_interface: unsafe extern "efiapi" fn(
+        handle:         &mut Handle,
+        protocol:       *const Guid,
+        interface_type: InterfaceType,
+        interface:      *mut c_void) -> RawStatus,
+
+    /// Reinstalls a protocol interface on a device handle
+    pub reinstall_protocol_interface: unsafe extern "efiapi" fn(
+        handle:        Handle,
+        protocol:      *const Guid,
+        old_interface: *mut c_void,
+        new_interface: *mut c_void) -> RawStatus,
+
+    /// Removes a protocol interface from a device handle
+    pub uninstall_protocol_interface: unsafe extern "efiapi" fn(
+        handle:    Handle,
+        protocol:  *const Guid,
+        interface: *mut c_void) -> RawStatus,
+
+    /// Queries a handle to determine if it supports a specified protocol
+    pub handle_protocol: unsafe extern "efiapi" fn(
+        handle:    Handle,
+        protocol:  *const Guid,
+        interface: &mut *mut c_void) -> RawStatus,
+
+    /// Reserved; must be null
+    _reserved: *const c_void,
+
+    /// Registers an event that is to be signaled whenever an interface is
+    /// installed for a specified protocol
+    pub register_protocol_notify: unsafe extern "efiapi" fn(
+        protocol:     *const Guid,
+        event:        Event,
+        registration: &mut *mut c_void) -> RawStatus,
+
+    /// Returns an array of handles that support a specified protocol
+    pub locate_handle: unsafe extern "efiapi" fn(
+        search_type: LocateSearchType,
+        protocol:    *const Guid,
+        search_key:  *const c_void,
+        buffer_size: &mut usize,
+        buffer:      *mut Handle) -> RawStatus,
+
+    /// Locates all devices on a device path that support a specified protocol
+    /// and returns the handle to the device that is closest to the path
+    pub locate_device_path: unsafe extern "efiapi" fn(
+        protocol:    *const Guid,
+        device_path: &mut *const DevicePath,
+        device:      &mut Handle) -> RawStatus,
+
+    /// Adds, updates, or removes a configuration table from the system table
+    pub install_configuration_table: unsafe extern "efiapi" fn(
+        guid:  *const Guid,
+        table: *const c_void) -> RawStatus,
+
+    /// Loads an EFI image into memory
+    pub load_image: unsafe extern "efiapi" fn(
+        boot_policy:         bool,
+        parent_image_handle: Handle,
+        device_path:         *const DevicePath,
+        source_buffer:       *const c_void,
+        source_size:         usize,
+        image_handle:        &mut Handle) -> RawStatus,
+
+    /// Transfers control to a loaded image's entry point
+    pub start_image: unsafe extern "efiapi" fn(
+        image_handle:   Handle,
+        exit_data_size: &mut usize,
+        exit_data:      &mut *mut u16) -> RawStatus,
+
+    /// Terminates a loaded EFI image and returns control to boot services
+    pub exit: unsafe extern "efiapi" fn(
+        image_handle:   Handle,
+        exit_status:    RawStatus,
+        exit_data_size: usize,
+        exit_data:      *const u16) -> RawStatus,
+
+    /// Unloads an image
+    pub unload_image: unsafe extern "efiapi" fn(
+        image_handle: Handle) -> RawStatus,
+
+    /// Terminates boot services
+    pub exit_boot_services: unsafe extern "efiapi" fn(
+        image_handle: Handle,
+        map_key:      usize) -> RawStatus,
+
+    /// Returns a monotonically increasing count for the platform
+    pub get_next_monotonic_count: unsafe extern "efiapi" fn(
+        count: &mut u64) -> RawStatus,
+
+    /// Stalls the processor for an amount of time
+    pub stall: unsafe extern "efiapi" fn(microseconds: usize) -> RawStatus,
+
+    /// Sets the system's watchdog timer
+    pub set_watchdog_timer: unsafe extern "efiapi" fn(
+        timeout:       usize,
+        watchdog_code: u64,
+        data_size:     usize,
+        watchdog_data: *const u16) -> RawStatus,
+
+    /// Connects one or more drivers to a controller
+    pub connect_controller: unsafe extern "efiapi" fn(
+        controller_handle:     Handle,
+        driver_image_handle:   *const Handle,
+        remaining_device_path: *const DevicePath,
+        recursive:             bool) -> RawStatus,
+
+    /// Disconnects one or more drivers from a controller
+    pub disconnect_controller: unsafe extern "efiapi" fn(
+        controller_handle:   Handle,
+        driver_image_handle: Handle,
+        child_handle:        Handle) -> RawStatus,
+
+    /// Queries a handle to determine if it supports a specified protocol. If
+    /// the protocol is supported by the handle, it opens the protocol on
+    /// behalf of the calling agent
+    pub open_protocol: unsafe extern "efiapi" fn(
+        handle:            Handle,
+        protocol:          *const Guid,
+        interface:         &mut *mut c_void,
+        agent_handle:      Handle,
+        controller_handle: Handle,
+        attributes:        u32) -> RawStatus,
+
+    /// Closes a protocol on a handle that was opened using `open_protocol()`
+    pub close_protocol: unsafe extern "efiapi" fn(
+        handle:            Handle,
+        protocol:          *const Guid,
+        agent_handle:      Handle,
+        controller_handle: Handle) -> RawStatus,
+
+    /// Retrieves the list of agents that currently have a protocol interface
+    /// opened
+    pub open_protocol_information: unsafe extern "efiapi" fn(
+        handle:       Handle,
+        protocol:     *const Guid,
+        entry_buffer: &mut *mut OpenProtocolInformationEntry,
+        entry_count:  &mut usize) -> RawStatus,
+
+    /// Retrieves the list of protocol interface GUIDs that are installed on a
+    /// handle in a buffer allocated from pool
+    pub protocols_per_handle: unsafe extern "efiapi" fn(
+        handle:                Handle,
+        protocol_buffer:       &mut *mut *mut Guid,
+        protocol_buffer_count: &mut usize) -> RawStatus,
+
+    /// Returns an array of handles that support the requested protocol in a
+    /// buffer allocated from pool
+    pub locate_handle_buffer: unsafe extern "efiapi" fn(
+        search_type: LocateSearchType,
+        protocol:    *const Guid,
+        search_key:  *const c_void,
+        no_handles:  &mut usize,
+        buffer:      &mut *mut Handle) -> RawStatus,
+
+    /// Returns the first protocol instance that matches the given protocol
+    pub locate_protocol: unsafe extern "efiapi" fn(
+        protocol:     *const Guid,
+        registration: *const c_void,
+        interface:    &mut *mut c_void) -> RawStatus,
+
+    /// Installs one or more protocol interfaces into the boot services
+    /// environment
+    pub install_multiple_protocol_interfaces: unsafe extern "efiapi" fn(
+        handle: &mut Handle, ...) -> RawStatus,
+
+    /// Removes one or more protocol interfaces from the boot services
+    /// environment
+    pub uninstall_multiple_protocol_interfaces: unsafe extern "efiapi" fn(
+        handle: Handle, ...) -> RawStatus,
+
+    /// Computes and returns a 32-bit CRC for a data buffer
+    pub calculate_crc32: unsafe extern "efiapi" fn(
+        data:      *const c_void,
+        data_size: usize,
+        crc32:     &mut u32) -> RawStatus,
+
+    /// Copies the contents of one buffer to another buffer
+    pub copy_mem: unsafe extern "efiapi" fn(
+        destination: *mut c_void,
+        source:      *const c_void,
+        length:      usize),
+
+    /// Fills a buffer with a specified value
+    pub set_mem: unsafe extern "efiapi" fn(
+        buffer: *mut c_void,
+        size:   usize,
+        value:  u8),
+
+    /// Creates an event in a group
+    pub create_event_ex: unsafe extern "efiapi" fn(
+        ty:             u32,
+        notify_tpl:     Tpl,
+        notify_func:    Option<EventNotify>,
+        notify_context: *const c_void,
+        event_group:    *const Guid,
+        event:          &mut Event) -> RawStatus,
+}
+
+impl BootServices {
+    /// Allocate `pages` 4 KiB pages of `memory_type` memory. `addr` is only
+    /// used by [`AllocateType::MaxAddress`] and [`AllocateType::Address`].
+    ///
+    /// Returns the physical address of the allocation
+    pub fn allocate_pages(&self, ty: AllocateType, memory_type: MemoryType,
+                          pages: usize, addr: PhysAddr)
+            -> Result<PhysAddr, status::Error> {
+        let mut memory = addr;
+        unsafe { (self.allocate_pages)(ty, memory_type, pages, &mut memory) }
+            .result()?;
+        Ok(memory)
+    }
+
+    /// Free `pages` pages allocated by [`Self::allocate_pages()`] at `addr`
+    ///
+    /// # Safety
+    ///
+    /// The pages must not be used afterwards, by us or by anyone they were
+    /// handed to.
+    pub unsafe fn free_pages(&self, addr: PhysAddr, pages: usize)
+            -> Result<(), status::Error> {
+        unsafe { (self.free_pages)(addr, pages) }.result()?;
+        Ok(())
+    }
+
+    /// Allocate `size` bytes of `memory_type` pool memory. The allocation is
+    /// 8-byte aligned.
+    pub fn allocate_pool(&self, memory_type: MemoryType, size: usize)
+            -> Result<*mut u8, status::Error> {
+        let mut buffer = core::ptr::null_mut();
+        unsafe { (self.allocate_pool)(memory_type, size, &mut buffer) }
+            .result()?;
+        Ok(buffer)
+    }
+
+    /// Free the pool memory at `buffer` allocated by [`Self::allocate_pool()`]
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must not be used afterwards.
+    pub unsafe fn free_pool(&self, buffer: *mut u8)
+            -> Result<(), status::Error> {
+        unsafe { (self.free_pool)(buffer) }.result()?;
+        Ok(())
+    }
+
+    /// Create an event of type `ty`. `notify_context` is passed to
+    /// `notify_func`.
+    ///
+    /// # Safety
+    ///
+    /// `notify_context` must stay valid for as long as the event exists, and
+    /// `notify_func` must be sound to call with it at `notify_tpl`.
+    pub unsafe fn create_event(&self, ty: u32, notify_tpl: Tpl,
+                               notify_func: Option<EventNotify>,
+                               notify_context: *mut c_void)
+            -> Result<Event, status::Error> {
+        let mut event = core::ptr::null();
+        unsafe {
+            (self.create_event)(ty, notify_tpl, notify_func, notify_context,
+                                &mut event)
+        }.result()?;
+        Ok(event)
+    }
+
+    /// Returns the first instance of the protocol `P` in the system
+    pub fn locate_protocol<P: Protocol>(&self)
+            -> Result<*mut P, status::Error> {
+        let mut interface = core::ptr::null_mut();
+        unsafe {
+            (self.locate_protocol)(&P::GUID, core::ptr::null(), &mut interface)
+        }.result()?;
+        Ok(interface as *mut P)
+    }
+
+    /// Returns the instance of the protocol `P` installed on `handle`
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be a valid handle. The interface is only valid while the
+    /// protocol stays installed on it, and never past `exit_boot_services()`.
+    pub unsafe fn handle_protocol<P: Protocol>(&self, handle: Handle)
+            -> Result<*mut P, status::Error> {
+        let mut interface = core::ptr::null_mut();
+        unsafe { (self.handle_protocol)(handle, &P::GUID, &mut interface) }
+            .result()?;
+        Ok(interface as *mut P)
+    }
+
+    /// Open the protocol `P` on `handle` on behalf of `agent`, which is
+    /// usually the handle of our image
+    ///
+    /// # Safety
+    ///
+    /// `handle`, `agent` and `controller` must be valid handles or null where
+    /// `attributes` allows it. The interface is only valid while the protocol
+    /// stays open, and never past `exit_boot_services()`.
+    pub unsafe fn open_protocol<P: Protocol>(&self, handle: Handle,
+                                             agent: Handle, controller: Handle,
+                                             attributes: u32)
+            -> Result<*mut P, status::Error> {
+        let mut interface = core::ptr::null_mut();
+        unsafe {
+            (self.open_protocol)(handle, &P::GUID, &mut interface, agent,
+                                 controller, attributes)
+        }.result()?;
+        Ok(interface as *mut P)
+    }
+
+    /// Returns all handles that support the protocol `P`
+    pub fn locate_handle_buffer<P: Protocol>(&self)
+            -> Result<HandleBuffer<'_>, status::Error> {
+        let mut count = 0;
+        let mut buffer = core::ptr::null_mut();
+        unsafe {
+            (self.locate_handle_buffer)(LocateSearchType::ByProtocol,
+                                        &P::GUID, core::ptr::null(),
+                                        &mut count, &mut buffer)
+        }.result()?;
+        Ok(HandleBuffer { boot_svc: self, buffer, count })
+    }
+
+    /// Stall the processor for at least `microseconds`
+    pub fn stall(&self, microseconds: usize) {
+        // Stall can't fail
+        let _ = unsafe { (self.stall)(microseconds) };
+    }
+
+    /// Set the watchdog timer to `timeout` seconds. A `timeout` of 0 disables
+    /// the timer.
+    pub fn set_watchdog_timer(&self, timeout: usize)
+            -> Result<(), status::Error> {
+        unsafe { (self.set_watchdog_timer)(timeout, 0, 0, core::ptr::null()) }
+            .result()?;
+        Ok(())
+    }
+}
+
+/// Array of handles allocated from pool by `locate_handle_buffer()`. The
+/// array is freed on drop.
+pub struct HandleBuffer<'a> {
+    /// Boot services the array is freed by
+    boot_svc: &'a BootServices,
+
+    /// Pointer to the array of handles
+    buffer: *mut Handle,
+
+    /// Number of handles in the array
+    count: usize,
+}
+
+impl Deref for HandleBuffer<'_> {
+    type Target = [Handle];
+    fn deref(&self) -> &Self::Target {
+        // The firmware may hand out no buffer at all if there's no handle
+        if self.buffer.is_null() { return &[]; }
+        unsafe { core::slice::from_raw_parts(self.buffer, self.count) }
+    }
+}
+
+impl Drop for HandleBuffer<'_> {
+    fn drop(&mut self) {
+        if self.buffer.is_null() { return; }
+        let _ = unsafe { self.boot_svc.free_pool(self.buffer as *mut u8) };
+    }
+}
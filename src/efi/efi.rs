@@ -1,7 +1,40 @@
 //! Generic EFI definitions to be used all over
 
+use core::fmt;
+use core::sync::atomic::{ AtomicPtr, Ordering };
 use crate::efi::*;
 
+/// The system table handed to `efi_main`, saved by [`register_system_table()`]
+static SYSTEM_TABLE: AtomicPtr<SystemTable> =
+    AtomicPtr::new(core::ptr::null_mut());
+
+/// Save the system table pointer so it's reachable from anywhere in the
+/// kernel, e.g. from the routines using the runtime services.
+///
+/// # Safety
+///
+/// `sys_table` must point to a valid system table that stays where it is for
+/// as long as the kernel runs.
+pub unsafe fn register_system_table(sys_table: *mut SystemTable) {
+    SYSTEM_TABLE.store(sys_table, Ordering::SeqCst);
+}
+
+/// Returns the system table saved by [`register_system_table()`]
+pub fn system_table() -> Option<&'static SystemTable> {
+    unsafe { SYSTEM_TABLE.load(Ordering::SeqCst).as_ref() }
+}
+
+/// Returns the runtime services of the registered system table
+pub fn runtime_services() -> Option<&'static RuntimeServices> {
+    system_table().and_then(|sys_table| sys_table.runtime_services())
+}
+
+/// Returns the configuration table identified by `guid` in the registered
+/// system table
+pub fn find_config_table(guid: &Guid) -> Option<*const usize> {
+    system_table().and_then(|sys_table| sys_table.find_config_table(guid))
+}
+
 /// Handle to any thing within the EFI spec
 pub type Handle = *const usize;
 
@@ -50,8 +83,27 @@ pub struct SystemTable {
     /// A firmware vendor specific revision of the system firmware
     pub fw_revision: u32,
 
-    /// Pointers to unused structures
-    _padding: [usize; 7],
+    /// Handle of the active console input device
+    pub con_in_handle: Handle,
+
+    /// Pointer to the EFI_SIMPLE_TEXT_INPUT_PROTOCOL interface of
+    /// `con_in_handle`
+    pub con_in: *const usize,
+
+    /// Handle of the active console output device
+    pub con_out_handle: Handle,
+
+    /// Pointer to the [`SimpleTextOutput`] interface of `con_out_handle`
+    pub con_out: *mut SimpleTextOutput,
+
+    /// Handle of the active standard error console device
+    pub stderr_handle: Handle,
+
+    /// Pointer to the [`SimpleTextOutput`] interface of `stderr_handle`
+    pub stderr: *mut SimpleTextOutput,
+
+    /// Pointer to the EFI Runtime Services Table
+    pub runtime_svc: *const RuntimeServices,
 
     /// Pointer to the EFI Boot Services Table
     pub boot_svc: *const BootServices,
@@ -63,6 +115,30 @@ pub struct SystemTable {
     pub cfg_tables: *const ConfigTable,
 }
 
+impl SystemTable {
+    /// Returns the runtime services table
+    pub fn runtime_services(&self) -> Option<&'static RuntimeServices> {
+        unsafe { self.runtime_svc.as_ref() }
+    }
+
+    /// Returns the configuration tables
+    pub fn config_tables(&self) -> &'static [ConfigTable] {
+        if self.cfg_tables.is_null() { return &[]; }
+        unsafe {
+            core::slice::from_raw_parts(self.cfg_tables, self.n_cfg_entries)
+        }
+    }
+
+    /// Returns the configuration table identified by `guid`
+    pub fn find_config_table(&self, guid: &Guid) -> Option<*const usize> {
+        self.config_tables().iter()
+            .find(|cfg| cfg.guid == *guid)
+            .map(|cfg| cfg.table)
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
 /// Contains a set of GUID/pointer pairs compromised of the `cfg_table` field in
 /// the [`SystemTable`]
 pub struct ConfigTable {
@@ -73,36 +149,7 @@ pub struct ConfigTable {
     pub table: *const usize,
 }
 
-#[derive(Debug)]
-#[repr(C)]
-/// Struct containing pointers to `get_memory_map()` and `exit_boot_services()`,
-/// padded to be aligned as defined by EFI_BOOT_SERVICES.
-pub struct BootServices {
-    /// The table header for this struct
-    pub hdr: TableHeader,
-
-    /// Pointers to unused functions
-    _padding1: [usize; 4],
-
-    /// Returns the current boot services memory map and memory map key
-    pub get_memory_map: unsafe fn(memory_map_size:    &mut usize,
-                                  memory_map:         *mut u8,
-                                  map_key:            &mut usize,
-                                  descriptor_size:    &mut usize,
-                                  descriptor_version: &mut u32) -> Status,
-
-
-    /// Pointers to unused functions
-    _padding2: [usize; 21],
-
-    /// Terminates boot services
-    pub exit_boot_services: unsafe fn(image_handle: Handle,
-                                      map_key:      usize) -> Status,
-
-    // Other services omitted
-}
-
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(C, packed)]
 #[allow(missing_docs)]
 /// UEFI defined global unique ID
@@ -119,3 +166,24 @@ impl Guid {
         Self { d1, d2, d3, d4 }
     }
 }
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Copy the fields out; references to packed fields aren't allowed
+        let (d1, d2, d3, d4) = (self.d1, self.d2, self.d3, self.d4);
+        write!(f, "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+               d1, d2, d3, d4[0], d4[1])?;
+        d4[2..].iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+/// An EFI protocol interface, identified by its GUID.
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` structures matching the layout of the
+/// protocol interface identified by `GUID`.
+pub unsafe trait Protocol {
+    /// GUID identifying the protocol
+    const GUID: Guid;
+}
@@ -0,0 +1,274 @@
+//! EFI_SIMPLE_FILE_SYSTEM_PROTOCOL and EFI_FILE_PROTOCOL, used to load files
+//! from the volume we were booted from
+
+use core::ffi::c_void;
+use crate::efi::*;
+
+/// Open the file for reading
+pub const FILE_MODE_READ: u64 = 0x0000000000000001;
+
+/// Open the file for writing
+pub const FILE_MODE_WRITE: u64 = 0x0000000000000002;
+
+/// Create the file if it doesn't exist
+pub const FILE_MODE_CREATE: u64 = 0x8000000000000000;
+
+/// Maximum length of a path we can open, in UCS-2 characters
+const MAX_PATH: usize = 256;
+
+/// Errors returned by the file system routines
+#[derive(Debug)]
+pub enum Error {
+    /// The device we were booted from has no file system
+    NoFileSystem(status::Error),
+
+    /// Couldn't open the volume
+    OpenVolumeFailed(status::Error),
+
+    /// The path is too long or can't be encoded in UCS-2
+    InvalidPath,
+
+    /// Couldn't open the file
+    OpenFailed(status::Error),
+
+    /// Couldn't get the information about the file
+    GetInfoFailed(status::Error),
+
+    /// Couldn't allocate memory for the file
+    AllocationFailed(status::Error),
+
+    /// Couldn't read the file
+    ReadFailed(status::Error),
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// Provides a minimal interface for file-type access to a device
+pub struct SimpleFileSystem {
+    /// Revision of the protocol
+    pub revision: u64,
+
+    /// Opens the root directory on the volume
+    pub open_volume: unsafe extern "efiapi" fn(
+        this: *mut SimpleFileSystem,
+        root: &mut *mut File) -> RawStatus,
+}
+
+unsafe impl Protocol for SimpleFileSystem {
+    const GUID: Guid = Guid::new(0x964e5b22, 0x6459, 0x11d2,
+        [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]);
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// Provides file based access to supported file systems. An instance of this
+/// is an open file or directory.
+pub struct File {
+    /// Revision of the protocol
+    pub revision: u64,
+
+    /// Opens a new file relative to this file's location
+    pub open: unsafe extern "efiapi" fn(
+        this:       *mut File,
+        new_handle: &mut *mut File,
+        file_name:  *const u16,
+        open_mode:  u64,
+        attributes: u64) -> RawStatus,
+
+    /// Closes the file handle
+    pub close: unsafe extern "efiapi" fn(this: *mut File) -> RawStatus,
+
+    /// Closes and deletes the file
+    pub delete: unsafe extern "efiapi" fn(this: *mut File) -> RawStatus,
+
+    /// Reads data from the file
+    pub read: unsafe extern "efiapi" fn(
+        this:        *mut File,
+        buffer_size: &mut usize,
+        buffer:      *mut c_void) -> RawStatus,
+
+    /// Writes data to the file
+    pub write: unsafe extern "efiapi" fn(
+        this:        *mut File,
+        buffer_size: &mut usize,
+        buffer:      *const c_void) -> RawStatus,
+
+    /// Returns the current file position
+    pub get_position: unsafe extern "efiapi" fn(
+        this:     *mut File,
+        position: &mut u64) -> RawStatus,
+
+    /// Sets the current file position
+    pub set_position: unsafe extern "efiapi" fn(
+        this:     *mut File,
+        position: u64) -> RawStatus,
+
+    /// Returns information about the file
+    pub get_info: unsafe extern "efiapi" fn(
+        this:             *mut File,
+        information_type: *const Guid,
+        buffer_size:      &mut usize,
+        buffer:           *mut c_void) -> RawStatus,
+
+    /// Sets information about the file
+    pub set_info: unsafe extern "efiapi" fn(
+        this:             *mut File,
+        information_type: *const Guid,
+        buffer_size:      usize,
+        buffer:           *const c_void) -> RawStatus,
+
+    /// Flushes all modified data associated with the file to the device
+    pub flush: unsafe extern "efiapi" fn(this: *mut File) -> RawStatus,
+
+    /// Asynchronous version of `open`
+    pub open_ex: unsafe extern "efiapi" fn(
+        this:       *mut File,
+        new_handle: &mut *mut File,
+        file_name:  *const u16,
+        open_mode:  u64,
+        attributes: u64,
+        token:      *mut c_void) -> RawStatus,
+
+    /// Asynchronous version of `read`
+    pub read_ex: unsafe extern "efiapi" fn(
+        this:  *mut File,
+        token: *mut c_void) -> RawStatus,
+
+    /// Asynchronous version of `write`
+    pub write_ex: unsafe extern "efiapi" fn(
+        this:  *mut File,
+        token: *mut c_void) -> RawStatus,
+
+    /// Asynchronous version of `flush`
+    pub flush_ex: unsafe extern "efiapi" fn(
+        this:  *mut File,
+        token: *mut c_void) -> RawStatus,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// Generic information about a file, followed by its null-terminated name
+pub struct FileInfo {
+    /// Size of this structure, including the file name
+    pub size: u64,
+
+    /// Size of the file in bytes
+    pub file_size: u64,
+
+    /// Amount of physical space the file consumes on the volume
+    pub physical_size: u64,
+
+    /// Time the file was created
+    pub create_time: Time,
+
+    /// Time the file was last accessed
+    pub last_access_time: Time,
+
+    /// Time the file was last modified
+    pub modification_time: Time,
+
+    /// Attribute bits of the file
+    pub attribute: u64,
+}
+
+impl FileInfo {
+    /// GUID identifying this information type
+    pub const GUID: Guid = Guid::new(0x09576e92, 0x6d3f, 0x11d2,
+        [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]);
+}
+
+/// Load the file at `path` on the volume our image was loaded from into
+/// `LoaderData` memory, which survives `exit_boot_services()`.
+///
+/// `path` is relative to the root of the volume; both `/` and `\` can be used
+/// as separators.
+///
+/// # Safety
+///
+/// `img_handle` must be the handle of our image, and the boot services must
+/// not have been exited.
+pub unsafe fn load_file(boot_svc: &BootServices, img_handle: Handle, path: &str)
+        -> Result<&'static [u8], Error> {
+    // Get the file system of the device we were loaded from
+    let fs = unsafe {
+        let image = boot_svc.handle_protocol::<LoadedImage>(img_handle)
+            .map_err(Error::NoFileSystem)?;
+        boot_svc.handle_protocol::<SimpleFileSystem>((*image).device_handle)
+            .map_err(Error::NoFileSystem)?
+    };
+
+    // Open the root directory of the volume
+    let mut root = core::ptr::null_mut();
+    unsafe { ((*fs).open_volume)(fs, &mut root) }.result()
+        .map_err(Error::OpenVolumeFailed)?;
+
+    // Open the file, always closing the root directory afterwards
+    let file = open(root, path);
+    unsafe { ((*root).close)(root) };
+    let file = file?;
+
+    // Read the file, always closing it afterwards
+    let data = read(boot_svc, file);
+    unsafe { ((*file).close)(file) };
+    data
+}
+
+/// Open the file at `path` relative to the directory `dir` for reading
+fn open(dir: *mut File, path: &str) -> Result<*mut File, Error> {
+    // Encode the path; the firmware only knows backslashes
+    let mut name = [0u16; MAX_PATH];
+    ucs2::encode(path, &mut name).ok_or(Error::InvalidPath)?;
+    name.iter_mut()
+        .filter(|c| **c == b'/' as u16)
+        .for_each(|c| *c = b'\\' as u16);
+
+    let mut file = core::ptr::null_mut();
+    unsafe {
+        ((*dir).open)(dir, &mut file, name.as_ptr(), FILE_MODE_READ, 0)
+    }.result().map_err(Error::OpenFailed)?;
+    Ok(file)
+}
+
+/// Read the whole `file` into freshly allocated `LoaderData` memory
+fn read(boot_svc: &BootServices, file: *mut File)
+        -> Result<&'static [u8], Error> {
+    // Get the size of the file. The info is followed by the file name, so
+    // leave some space for it
+    let mut info = [0u64; 128];
+    let mut info_size = core::mem::size_of_val(&info);
+    unsafe {
+        ((*file).get_info)(file, &FileInfo::GUID, &mut info_size,
+                           info.as_mut_ptr() as *mut c_void)
+    }.result().map_err(Error::GetInfoFailed)?;
+    let size = unsafe { (*(info.as_ptr() as *const FileInfo)).file_size }
+        as usize;
+
+    // Allocate the memory for the file, at least one page so even empty files
+    // get a valid address
+    let pages = size.div_ceil(4096).max(1);
+    let buffer = boot_svc.allocate_pages(AllocateType::AnyPages,
+                                         MemoryType::LoaderData, pages, 0)
+        .map_err(Error::AllocationFailed)? as *mut u8;
+
+    // Read the file. The firmware may return less than we asked for
+    let mut read = 0;
+    while read < size {
+        let mut chunk = size - read;
+        let status = unsafe {
+            ((*file).read)(file, &mut chunk,
+                           buffer.add(read) as *mut c_void)
+        }.result();
+
+        // Nobody gets to see a partially read file, so give its memory back
+        if let Err(err) = status {
+            let _ = unsafe { boot_svc.free_pages(buffer as u64, pages) };
+            return Err(Error::ReadFailed(err));
+        }
+
+        // Make sure we don't loop forever on a file that shrunk
+        if chunk == 0 { break; }
+        read += chunk;
+    }
+
+    Ok(unsafe { core::slice::from_raw_parts(buffer, read) })
+}
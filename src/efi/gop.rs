@@ -0,0 +1,270 @@
+//! EFI_GRAPHICS_OUTPUT_PROTOCOL, used to find a linear framebuffer
+
+use core::ffi::c_void;
+use crate::efi::*;
+
+/// Errors returned by the graphics output routines
+#[derive(Debug)]
+pub enum Error {
+    /// There's no graphics output device
+    NoGraphicsOutput(status::Error),
+
+    /// Couldn't query a mode
+    QueryModeFailed(status::Error),
+
+    /// Couldn't set a mode
+    SetModeFailed(status::Error),
+
+    /// There's no mode with a linear framebuffer of the requested resolution
+    ModeNotFound,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Layout of a pixel in the framebuffer
+pub enum PixelFormat {
+    /// Byte 0 is red, byte 1 is green, byte 2 is blue, byte 3 is reserved
+    Rgb,
+
+    /// Byte 0 is blue, byte 1 is green, byte 2 is red, byte 3 is reserved
+    Bgr,
+
+    /// The layout is described by the [`PixelBitmask`] of the mode
+    Bitmask,
+
+    /// There's no linear framebuffer; only `blt()` can be used
+    BltOnly,
+
+    /// Pixel format not defined by the spec
+    Unsupported,
+}
+
+impl From<u32> for PixelFormat {
+    fn from(val: u32) -> PixelFormat {
+        match val {
+            0 => PixelFormat::Rgb,
+            1 => PixelFormat::Bgr,
+            2 => PixelFormat::Bitmask,
+            3 => PixelFormat::BltOnly,
+            _ => PixelFormat::Unsupported,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+/// Bits of a pixel used by the individual colors
+pub struct PixelBitmask {
+    /// Bits used by red
+    pub red: u32,
+
+    /// Bits used by green
+    pub green: u32,
+
+    /// Bits used by blue
+    pub blue: u32,
+
+    /// Reserved bits
+    pub reserved: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+/// Information about a graphics mode
+pub struct ModeInformation {
+    /// Version of this structure
+    pub version: u32,
+
+    /// Size of the screen in pixels in the X dimension
+    pub horizontal_resolution: u32,
+
+    /// Size of the screen in pixels in the Y dimension
+    pub vertical_resolution: u32,
+
+    /// Raw layout of a pixel; see [`Self::pixel_format()`]
+    pub pixel_format: u32,
+
+    /// Bits used by the colors if the format is [`PixelFormat::Bitmask`]
+    pub pixel_information: PixelBitmask,
+
+    /// Number of pixels per video memory line
+    pub pixels_per_scan_line: u32,
+}
+
+impl ModeInformation {
+    /// Returns the layout of a pixel
+    pub fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::from(self.pixel_format)
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// The current mode of a graphics output device
+pub struct GraphicsOutputMode {
+    /// Number of modes supported by the device
+    pub max_mode: u32,
+
+    /// The current mode
+    pub mode: u32,
+
+    /// Information about the current mode
+    pub info: *const ModeInformation,
+
+    /// Size of `info`
+    pub size_of_info: usize,
+
+    /// Physical address of the linear framebuffer
+    pub frame_buffer_base: PhysAddr,
+
+    /// Size of the linear framebuffer in bytes
+    pub frame_buffer_size: usize,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// Provides a basic abstraction to set video modes and copy pixels to and from
+/// the graphics controller's frame buffer
+pub struct GraphicsOutput {
+    /// Returns information for an available graphics mode
+    pub query_mode: unsafe extern "efiapi" fn(
+        this:         *mut GraphicsOutput,
+        mode_number:  u32,
+        size_of_info: &mut usize,
+        info:         &mut *mut ModeInformation) -> RawStatus,
+
+    /// Sets the video device into the specified mode and clears the visible
+    /// portions of the output display to black
+    pub set_mode: unsafe extern "efiapi" fn(
+        this:        *mut GraphicsOutput,
+        mode_number: u32) -> RawStatus,
+
+    /// Blits a rectangle of pixels
+    pub blt: unsafe extern "efiapi" fn(
+        this:          *mut GraphicsOutput,
+        blt_buffer:    *mut c_void,
+        blt_operation: u32,
+        source_x:      usize,
+        source_y:      usize,
+        destination_x: usize,
+        destination_y: usize,
+        width:         usize,
+        height:        usize,
+        delta:         usize) -> RawStatus,
+
+    /// The current mode of the device
+    pub mode: *const GraphicsOutputMode,
+}
+
+unsafe impl Protocol for GraphicsOutput {
+    const GUID: Guid = Guid::new(0x9042a9de, 0x23dc, 0x4a38,
+        [0x96, 0xfb, 0x7a, 0xde, 0xd0, 0x80, 0x51, 0x6a]);
+}
+
+/// A linear framebuffer
+#[derive(Debug, Copy, Clone)]
+pub struct Framebuffer {
+    /// Physical address of the framebuffer
+    pub base: usize,
+
+    /// Size of the framebuffer in bytes
+    pub size: usize,
+
+    /// Width of the screen in pixels
+    pub width: usize,
+
+    /// Height of the screen in pixels
+    pub height: usize,
+
+    /// Number of pixels per line in memory
+    pub stride: usize,
+
+    /// Layout of a pixel
+    pub format: PixelFormat,
+
+    /// Bits used by the colors if the format is [`PixelFormat::Bitmask`]
+    pub mask: PixelBitmask,
+}
+
+impl GraphicsOutput {
+    /// Returns the information about mode `mode`
+    pub fn query_mode(&mut self, boot_svc: &BootServices, mode: u32)
+            -> Result<ModeInformation, status::Error> {
+        let mut size = 0;
+        let mut info = core::ptr::null_mut();
+        unsafe { (self.query_mode)(self, mode, &mut size, &mut info) }
+            .result()?;
+
+        // The info is allocated from pool by the firmware
+        let ret = unsafe { *info };
+        let _ = unsafe { boot_svc.free_pool(info as *mut u8) };
+        Ok(ret)
+    }
+
+    /// Set the device to mode `mode`
+    pub fn set_mode(&mut self, mode: u32) -> Result<(), status::Error> {
+        unsafe { (self.set_mode)(self, mode) }.result()?;
+        Ok(())
+    }
+
+    /// Returns the linear framebuffer of the current mode, if it has one
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        let mode = unsafe { &*self.mode };
+        let info = unsafe { &*mode.info };
+
+        match info.pixel_format() {
+            PixelFormat::Rgb | PixelFormat::Bgr | PixelFormat::Bitmask => {},
+            _ => return None,
+        }
+
+        Some(Framebuffer {
+            base:   mode.frame_buffer_base as usize,
+            size:   mode.frame_buffer_size,
+            width:  info.horizontal_resolution as usize,
+            height: info.vertical_resolution as usize,
+            stride: info.pixels_per_scan_line as usize,
+            format: info.pixel_format(),
+            mask:   info.pixel_information,
+        })
+    }
+}
+
+/// Find the graphics output device and set it to a mode with a linear
+/// framebuffer of `resolution` (width, height). If no resolution is requested,
+/// the current mode is kept if it has a linear framebuffer.
+///
+/// Returns the framebuffer of the selected mode.
+pub fn select_mode(boot_svc: &BootServices,
+                   resolution: Option<(usize, usize)>)
+        -> Result<Framebuffer, Error> {
+    let gop = unsafe {
+        &mut *boot_svc.locate_protocol::<GraphicsOutput>()
+            .map_err(Error::NoGraphicsOutput)?
+    };
+
+    // Keep the current mode if it works for us
+    if resolution.is_none() && let Some(fb) = gop.framebuffer() {
+        return Ok(fb);
+    }
+
+    // Go through the modes and pick the first one that matches. Without a
+    // requested resolution, that's the first one with a linear framebuffer
+    let max_mode = unsafe { (*gop.mode).max_mode };
+    for mode in 0..max_mode {
+        let info = gop.query_mode(boot_svc, mode)
+            .map_err(Error::QueryModeFailed)?;
+
+        match info.pixel_format() {
+            PixelFormat::Rgb | PixelFormat::Bgr | PixelFormat::Bitmask => {},
+            _ => continue,
+        }
+
+        let size = (info.horizontal_resolution as usize,
+                    info.vertical_resolution as usize);
+        if resolution.is_some_and(|res| res != size) { continue; }
+
+        gop.set_mode(mode).map_err(Error::SetModeFailed)?;
+        return gop.framebuffer().ok_or(Error::ModeNotFound);
+    }
+
+    Err(Error::ModeNotFound)
+}
@@ -0,0 +1,42 @@
+//! Well-known GUIDs of configuration tables
+
+use crate::efi::Guid;
+
+/// ACPI 2.0 or newer RSDP
+pub const ACPI_20_TABLE: Guid = Guid::new(0x8868e871, 0xe4f1, 0x11d3,
+    [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81]);
+
+/// ACPI 1.0 RSDP
+pub const ACPI_TABLE: Guid = Guid::new(0xeb9d2d30, 0x2d88, 0x11d3,
+    [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]);
+
+/// SMBIOS entry point structure
+pub const SMBIOS_TABLE: Guid = Guid::new(0xeb9d2d31, 0x2d88, 0x11d3,
+    [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]);
+
+/// SMBIOS 3.0 entry point structure
+pub const SMBIOS3_TABLE: Guid = Guid::new(0xf2fd1544, 0x9794, 0x4a2c,
+    [0x99, 0x2e, 0xe5, 0xbb, 0xcf, 0x20, 0xe3, 0x94]);
+
+/// EFI memory attributes table, describing the runtime memory protections
+pub const MEMORY_ATTRIBUTES_TABLE: Guid = Guid::new(0xdcfa911d, 0x26eb, 0x469f,
+    [0xa2, 0x20, 0x38, 0xb7, 0xdc, 0x46, 0x12, 0x20]);
+
+/// Flattened device tree blob
+pub const DEVICE_TREE: Guid = Guid::new(0xb1b621d5, 0xf19c, 0x41a5,
+    [0x83, 0x0b, 0xd9, 0x15, 0x2c, 0x69, 0xaa, 0xe0]);
+
+/// Registry of the well-known GUIDs and their names
+pub const KNOWN: &[(Guid, &str)] = &[
+    (ACPI_20_TABLE,           "ACPI 2.0"),
+    (ACPI_TABLE,              "ACPI 1.0"),
+    (SMBIOS_TABLE,            "SMBIOS"),
+    (SMBIOS3_TABLE,           "SMBIOS3"),
+    (MEMORY_ATTRIBUTES_TABLE, "Memory Attributes"),
+    (DEVICE_TREE,             "Device Tree"),
+];
+
+/// Returns the name of a well-known `guid`
+pub fn name(guid: &Guid) -> Option<&'static str> {
+    KNOWN.iter().find(|(known, _)| known == guid).map(|&(_, name)| name)
+}
@@ -0,0 +1,70 @@
+//! EFI_LOADED_IMAGE_PROTOCOL, describing the image we were loaded from
+
+use core::ffi::c_void;
+use crate::efi::*;
+
+#[derive(Debug)]
+#[repr(C)]
+/// Information about a loaded image, installed on its image handle
+pub struct LoadedImage {
+    /// Revision of the protocol
+    pub revision: u32,
+
+    /// Handle of the image that loaded this one
+    pub parent_handle: Handle,
+
+    /// The system table of this image
+    pub sys_table: *const SystemTable,
+
+    /// Handle of the device the image was loaded from
+    pub device_handle: Handle,
+
+    /// File path of the image on `device_handle`
+    pub file_path: *const DevicePath,
+
+    /// Reserved; must be null
+    _reserved: *const c_void,
+
+    /// Size of `load_options` in bytes
+    pub load_options_size: u32,
+
+    /// The image's load options; a UCS-2 command line for applications
+    /// started from the boot manager or the shell
+    pub load_options: *const c_void,
+
+    /// Base address the image was loaded at
+    pub image_base: *const c_void,
+
+    /// Size of the loaded image in bytes
+    pub image_size: u64,
+
+    /// Memory type the code sections were loaded as
+    pub image_code_type: u32,
+
+    /// Memory type the data sections were loaded as
+    pub image_data_type: u32,
+
+    /// Unloads the image
+    pub unload: Option<unsafe extern "efiapi" fn(
+        image_handle: Handle) -> RawStatus>,
+}
+
+unsafe impl Protocol for LoadedImage {
+    const GUID: Guid = Guid::new(0x5b1b31a1, 0x9562, 0x11d2,
+        [0x8e, 0x3f, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]);
+}
+
+impl LoadedImage {
+    /// Returns the load options as a UCS-2 string, up to the first null
+    pub fn load_options(&self) -> &[u16] {
+        if self.load_options.is_null() { return &[]; }
+
+        let options = unsafe {
+            core::slice::from_raw_parts(self.load_options as *const u16,
+                                        self.load_options_size as usize / 2)
+        };
+        let len = options.iter().position(|&c| c == 0)
+            .unwrap_or(options.len());
+        &options[..len]
+    }
+}
@@ -1,7 +1,28 @@
 //! Memory related definitions
 
+use core::sync::atomic::{ AtomicBool, Ordering };
 use crate::efi::*;
 use crate::rangeset::{ Range, RangeSet };
+use crate::spinlock::SpinLock;
+
+/// The memory map as it was when the boot services were exited.
+///
+/// This is set by [`memory_map_exit()`].
+pub static MEMORY_MAP: SpinLock<Option<MemoryMap>> = SpinLock::new(None);
+
+/// Returns the memory map saved by [`memory_map_exit()`]
+pub fn memory_map() -> Option<MemoryMap> {
+    *MEMORY_MAP.lock()
+}
+
+/// Set by [`memory_map_exit()`] right before it exits the boot services
+static BOOT_SERVICES_EXITED: AtomicBool = AtomicBool::new(false);
+
+/// Returns whether the boot services have been exited, in which case neither
+/// they nor any protocol they handed out may be used anymore
+pub fn boot_services_exited() -> bool {
+    BOOT_SERVICES_EXITED.load(Ordering::SeqCst)
+}
 
 /// Errors possibly returned by EFI routines
 #[derive(Debug)]
@@ -9,19 +30,31 @@ pub enum Error {
     /// Memory map expected a larger array
     WrongMemoryMapSize(usize),
 
+    /// `get_memory_map()` failed for a reason other than a small buffer
+    GetMemoryMapFailed(status::Error),
+
+    /// Couldn't allocate the buffer for the memory map
+    AllocationFailed(status::Error),
+
     /// Couldn't exit the boot services
     ExitBootSvcFailed,
 
     /// Some calculation overflowed while creating the free memory map
     MemoryMapOverflow,
+
+    /// The firmware reported a descriptor size smaller than the descriptor
+    WrongDescriptorSize(usize),
 }
 
 #[derive(Debug, Copy, Clone)]
-#[repr(C, align(16))]
-/// Descriptors returned by `get_memory_map()`
+#[repr(C)]
+/// Descriptors returned by `get_memory_map()`.
+///
+/// The firmware is free to return descriptors larger than this struct, so the
+/// map has to be walked with the descriptor size it reports; see [`MemoryMap`].
 pub struct MemoryDescriptor {
-    /// Type of the memory region
-    pub mem_type: MemoryType,
+    /// Raw type of the memory region; see [`Self::mem_type()`]
+    pub ty: u32,
 
     /// Physical address of the first byte in the memory region
     pub phys_addr: usize,
@@ -35,19 +68,79 @@ pub struct MemoryDescriptor {
     /// Attributes of the memory region that describe the bit mask of
     /// capabilities for that memory region, and not necessarily the current
     /// settings for that memory region
-    _attribute: u64,
+    pub attribute: MemoryAttribute,
 }
 
 impl MemoryDescriptor {
-    /// Returns a memory descriptor whose byte map is filled with 0s.
-    const fn empty() -> Self {
-        MemoryDescriptor {
-            mem_type: MemoryType::Reserved,
-            phys_addr: 0,
-            virt_addr: 0,
-            n_pages: 0,
-            _attribute: 0,
-        }
+    /// Returns the type of the memory region
+    pub fn mem_type(&self) -> MemoryType {
+        MemoryType::from(self.ty)
+    }
+
+    /// Returns the inclusive range of physical memory this descriptor covers,
+    /// or `None` if the descriptor is empty or overflows.
+    pub fn range(&self) -> Option<Range> {
+        let size = (self.n_pages as usize).checked_mul(4096)?;
+        let end = self.phys_addr.checked_add(size.checked_sub(1)?)?;
+        Range::new(self.phys_addr, end).ok()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(transparent)]
+/// Bit mask of memory region attributes
+pub struct MemoryAttribute(pub u64);
+
+impl MemoryAttribute {
+    /// Supports being configured as not cacheable
+    pub const UC: Self = Self(0x0000000000000001);
+
+    /// Supports being configured as write combining
+    pub const WC: Self = Self(0x0000000000000002);
+
+    /// Supports being configured as cacheable with a write through policy
+    pub const WT: Self = Self(0x0000000000000004);
+
+    /// Supports being configured as cacheable with a write back policy
+    pub const WB: Self = Self(0x0000000000000008);
+
+    /// Supports being configured as not cacheable, exported, and supports the
+    /// fetch and add semaphore mechanism
+    pub const UCE: Self = Self(0x0000000000000010);
+
+    /// Supports being configured as write-protected
+    pub const WP: Self = Self(0x0000000000001000);
+
+    /// Supports being configured as read-protected
+    pub const RP: Self = Self(0x0000000000002000);
+
+    /// Supports being configured so it is protected from executing code
+    pub const XP: Self = Self(0x0000000000004000);
+
+    /// Refers to persistent memory
+    pub const NV: Self = Self(0x0000000000008000);
+
+    /// Has higher reliability relative to other memory in the system
+    pub const MORE_RELIABLE: Self = Self(0x0000000000010000);
+
+    /// Supports being configured as read-only
+    pub const RO: Self = Self(0x0000000000020000);
+
+    /// Is earmarked for specific purposes such as specific device drivers or
+    /// applications
+    pub const SP: Self = Self(0x0000000000040000);
+
+    /// Is capable of being protected with the CPU's memory cryptographic
+    /// capabilities
+    pub const CPU_CRYPTO: Self = Self(0x0000000000080000);
+
+    /// Needs to be given a virtual mapping by the OS when
+    /// `set_virtual_address_map()` is called
+    pub const RUNTIME: Self = Self(0x8000000000000000);
+
+    /// Returns whether all the bits in `other` are set
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
     }
 }
 
@@ -105,6 +198,9 @@ pub enum MemoryType {
     /// non-volatility
     PersistentMemory,
 
+    /// Memory that must be accepted by the boot target before it can be used
+    UnacceptedMemory,
+
     /// Memory type not supported by our system whatsoever
     Unsupported,
 }
@@ -141,12 +237,126 @@ impl From<u32> for MemoryType {
             12 => MemoryType::MemoryMappedIOPortSpace,
             13 => MemoryType::PalCode,
             14 => MemoryType::PersistentMemory,
+            15 => MemoryType::UnacceptedMemory,
             _  => MemoryType::Unsupported,
         }
     }
 }
 
-/// Get a memory map of [`MemoryDescriptor`]s and exit the boot services
+/// The memory map returned by `get_memory_map()`
+#[derive(Debug, Copy, Clone)]
+pub struct MemoryMap {
+    /// The raw memory map
+    buffer: &'static [u8],
+
+    /// Size of a single descriptor in the map
+    desc_size: usize,
+
+    /// Version of the descriptors in the map
+    desc_version: u32,
+}
+
+impl MemoryMap {
+    /// Create a memory map from the `size` bytes at `buffer` returned by
+    /// `get_memory_map()`.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must point to `size` readable bytes and must never be freed.
+    pub unsafe fn new(buffer: *const u8, size: usize, desc_size: usize,
+                      desc_version: u32) -> Result<Self, Error> {
+        // Make sure we can read a full descriptor at each stride
+        if desc_size < core::mem::size_of::<MemoryDescriptor>() {
+            return Err(Error::WrongDescriptorSize(desc_size));
+        }
+
+        Ok(Self {
+            buffer: unsafe { core::slice::from_raw_parts(buffer, size) },
+            desc_size,
+            desc_version,
+        })
+    }
+
+    /// Returns the number of descriptors in the map
+    pub fn len(&self) -> usize {
+        self.buffer.len() / self.desc_size
+    }
+
+    /// Returns whether the map has no descriptors
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the size of a single descriptor in the map
+    pub fn desc_size(&self) -> usize {
+        self.desc_size
+    }
+
+    /// Returns the version of the descriptors in the map
+    pub fn desc_version(&self) -> u32 {
+        self.desc_version
+    }
+
+    /// Returns the raw memory map, e.g. for `set_virtual_address_map()`
+    pub fn as_bytes(&self) -> &'static [u8] {
+        self.buffer
+    }
+
+    /// Returns an iterator over the descriptors in the map
+    pub fn iter(&self) -> impl Iterator<Item = MemoryDescriptor> + 'static {
+        self.buffer.chunks_exact(self.desc_size).map(|desc| unsafe {
+            // The buffer is only guaranteed to be 8-byte aligned
+            (desc.as_ptr() as *const MemoryDescriptor).read_unaligned()
+        })
+    }
+
+    /// Returns the descriptor of the region containing `addr`
+    pub fn find(&self, addr: usize) -> Option<MemoryDescriptor> {
+        let addr = Range::new(addr, addr).ok()?;
+        self.iter().find(|desc| {
+            desc.range().is_some_and(|range| range.contains(&addr))
+        })
+    }
+
+    /// Returns the memory that is free to use after the boot services have
+    /// been exited
+    pub fn free_memory(&self) -> Result<RangeSet, Error> {
+        let mut free_memory: RangeSet = RangeSet::new();
+        for desc in self.iter() {
+            // Make sure we're free to use this memory
+            if !desc.mem_type().available_post_boot_svc_exit() { continue; }
+
+            // Skip empty descriptors
+            if desc.n_pages == 0 { continue; }
+
+            // Calculate the range of this memory
+            let range = desc.range().ok_or(Error::MemoryMapOverflow)?;
+
+            // Write the memory down. I make the assumption this shit will never
+            // return errors because I'm just that cool B)
+            free_memory.insert(range).unwrap();
+        }
+
+        // Make the null byte impossible to be allocated
+        let _ = free_memory.remove(Range::new(0, 1).unwrap());
+
+        Ok(free_memory)
+    }
+}
+
+/// Number of descriptors the memory map buffer is over-allocated by. The
+/// allocation of the buffer itself can add descriptors to the map and the map
+/// can change between a failed `exit_boot_services()` and the retry, at which
+/// point we're no longer allowed to allocate a larger buffer.
+const MEMORY_MAP_SLACK: usize = 32;
+
+/// Maximum number of times `exit_boot_services()` is retried with a fresh
+/// memory map key
+const EXIT_RETRIES: usize = 8;
+
+/// Get a memory map of [`MemoryDescriptor`]s and exit the boot services.
+///
+/// The map is saved in [`MEMORY_MAP`] and the memory free for use is returned.
 pub unsafe fn memory_map_exit(
     image_handle: Handle,
     sys_table: *mut SystemTable
@@ -156,58 +366,84 @@ pub unsafe fn memory_map_exit(
     let get_memory_map = boot_svc.get_memory_map;
     let exit_boot_services = boot_svc.exit_boot_services;
 
-    // Create the arguments for the call. We expect at most `N_MEM_DESC`
-    // `MemoryDescriptor`s.
-    const N_MEM_DESC: usize = 2048;
-    let mut memory_map = [MemoryDescriptor::empty(); N_MEM_DESC];
-
-    let mut size = core::mem::size_of_val(&memory_map);
+    let mut size = 0;
     let mut key = 0;
     let mut desc_size = 0;
     let mut desc_version = 0;
 
-    // Populate the memory map
+    // Query the size of the memory map
     let ret = unsafe {
-        get_memory_map(&mut size, memory_map.as_mut_ptr() as *mut u8, &mut key,
+        get_memory_map(&mut size, core::ptr::null_mut(), &mut key,
                        &mut desc_size, &mut desc_version)
     };
+    match ret.result() {
+        Ok(_) | Err(status::Error::BufferTooSmall) => {},
+        Err(err) => return Err(Error::GetMemoryMapFailed(err)),
+    }
 
-    // Make sure we got the map
-    if ret != Status::Success { return Err(Error::WrongMemoryMapSize(size)); }
+    // Allocate the buffer for the memory map. It's allocated as `LoaderData`
+    // so it stays intact after we exit the boot services
+    let mut capacity = size + MEMORY_MAP_SLACK * desc_size;
+    let mut buffer = boot_svc.allocate_pool(MemoryType::LoaderData, capacity)
+        .map_err(Error::AllocationFailed)?;
+
+    // Get the memory map and exit the boot services. The spec allows
+    // `exit_boot_services()` to fail if the map changed since we got it, in
+    // which case we have to get the map again and retry
+    let mut exit_attempts = 0;
+    loop {
+        // Populate the memory map
+        size = capacity;
+        let ret = unsafe {
+            get_memory_map(&mut size, buffer, &mut key,
+                           &mut desc_size, &mut desc_version)
+        };
+
+        // Make sure we got the map
+        match ret.result() {
+            Ok(_) => {},
+
+            // The map has grown. Until we try to exit the boot services, we're
+            // allowed to allocate a larger buffer
+            Err(status::Error::BufferTooSmall) if exit_attempts == 0 => {
+                unsafe { boot_svc.free_pool(buffer) }
+                    .map_err(Error::AllocationFailed)?;
+                capacity = size + MEMORY_MAP_SLACK * desc_size;
+                buffer = boot_svc.allocate_pool(MemoryType::LoaderData,
+                                                capacity)
+                    .map_err(Error::AllocationFailed)?;
+                continue;
+            },
+
+            Err(status::Error::BufferTooSmall) => {
+                return Err(Error::WrongMemoryMapSize(size));
+            },
+
+            Err(err) => return Err(Error::GetMemoryMapFailed(err)),
+        }
 
-    // Transmute the byte array to an array of descriptors
+        // Exit the boot services. Even a failed attempt may have torn down
+        // some of them, so stop everyone from using them from here on
+        BOOT_SERVICES_EXITED.store(true, Ordering::SeqCst);
+        let ret = unsafe { exit_boot_services(image_handle, key) };
+        exit_attempts += 1;
+
+        // Make sure we have exited successfully. An invalid parameter means
+        // our map key is stale
+        match ret.result() {
+            Ok(_) => break,
+            Err(status::Error::InvalidParameter)
+                if exit_attempts < EXIT_RETRIES => continue,
+            Err(_) => return Err(Error::ExitBootSvcFailed),
+        }
+    }
+
+    // Save the map; the buffer is `LoaderData`, so it's never freed
     let memory_map = unsafe {
-        core::slice::from_raw_parts(
-            memory_map.as_ptr(),
-            size / core::mem::size_of::<MemoryDescriptor>())
+        MemoryMap::new(buffer, size, desc_size, desc_version)?
     };
-
-    // Exit the boot services
-    let ret = unsafe { exit_boot_services(image_handle, key) };
-
-    // Make sure we have exited successfully
-    if ret != Status::Success { return Err(Error::ExitBootSvcFailed); }
+    *MEMORY_MAP.lock() = Some(memory_map);
 
     // Now, only retain the memory that we're free to use in a memory allocator
-    let mut free_memory: RangeSet = RangeSet::new();
-    for desc in memory_map.iter() {
-        // Make sure we're free to use this memory
-        if !desc.mem_type.available_post_boot_svc_exit() { continue; }
-
-        // Calculate the end of this memory
-        let offset = (desc.n_pages as usize).checked_mul(4096)
-            .ok_or(Error::MemoryMapOverflow)?;
-        let end = desc.phys_addr.checked_add(offset - 1)
-            .ok_or(Error::MemoryMapOverflow)?;
-
-        // Write the memory down. I make the assumption this shit will never
-        // return errors because I'm just that cool B)
-        free_memory.insert(Range::new(desc.phys_addr, end).unwrap()).unwrap();
-    }
-
-    // Make the null byte impossible to be allocated
-    let _ = free_memory.remove(Range::new(0, 1).unwrap());
-
-    // Return the free memory map
-    Ok(free_memory)
+    memory_map.free_memory()
 }
@@ -1,12 +1,28 @@
 //! EFI definitions
 
 pub mod efi;
+pub mod boot_svc;
+pub mod runtime_svc;
 pub mod memory;
 pub mod status;
+pub mod guid;
+pub mod table;
+pub mod ucs2;
+pub mod loaded_image;
+pub mod fs;
+pub mod gop;
+pub mod text;
+pub mod rng;
+pub mod mp;
+pub mod pxe;
 
 pub use efi::*;
+pub use boot_svc::*;
+pub use runtime_svc::*;
 pub use memory::*;
 pub use status::*;
+pub use loaded_image::*;
+pub use text::*;
 
 /// Errors that can be possibly returned by memory routines
 #[derive(Debug)]
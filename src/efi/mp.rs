@@ -0,0 +1,197 @@
+//! EFI_MP_SERVICES_PROTOCOL, the firmware's interface to the application
+//! processors
+
+use core::ffi::c_void;
+use crate::efi::*;
+
+/// The processor is the BSP
+pub const PROCESSOR_AS_BSP_BIT: u32 = 0x01;
+
+/// The processor is enabled
+pub const PROCESSOR_ENABLED_BIT: u32 = 0x02;
+
+/// The processor passed its health check
+pub const PROCESSOR_HEALTH_STATUS_BIT: u32 = 0x04;
+
+/// Function run on an application processor
+pub type ApProcedure = extern "efiapi" fn(argument: *mut c_void);
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+/// Physical location of a logical processor
+pub struct CpuPhysicalLocation {
+    /// Zero-based physical package number
+    pub package: u32,
+
+    /// Zero-based physical core number within the package
+    pub core: u32,
+
+    /// Zero-based logical thread number within the core
+    pub thread: u32,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+/// Extended physical location of a logical processor. Only filled in by
+/// newer firmware when explicitly asked for; we never do.
+pub struct CpuPhysicalLocation2 {
+    /// Zero-based physical package number
+    pub package: u32,
+
+    /// Zero-based physical module number within the package
+    pub module: u32,
+
+    /// Zero-based physical tile number within the module
+    pub tile: u32,
+
+    /// Zero-based physical die number within the package
+    pub die: u32,
+
+    /// Zero-based physical core number within the die
+    pub core: u32,
+
+    /// Zero-based logical thread number within the core
+    pub thread: u32,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+/// Information about a logical processor
+pub struct ProcessorInformation {
+    /// The APIC ID of the processor
+    pub processor_id: u64,
+
+    /// `PROCESSOR_*_BIT` flags describing the state of the processor
+    pub status_flag: u32,
+
+    /// Physical location of the processor
+    pub location: CpuPhysicalLocation,
+
+    /// Extended physical location of the processor
+    pub extended_information: CpuPhysicalLocation2,
+}
+
+impl ProcessorInformation {
+    /// Returns whether this is the BSP
+    pub fn is_bsp(&self) -> bool {
+        self.status_flag & PROCESSOR_AS_BSP_BIT != 0
+    }
+
+    /// Returns whether the processor is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.status_flag & PROCESSOR_ENABLED_BIT != 0
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// Provides the services required to manage the processors. Must only be used
+/// from the BSP.
+pub struct MpServices {
+    /// Returns the number of logical processors and the number of enabled ones
+    pub get_number_of_processors: unsafe extern "efiapi" fn(
+        this:                          *mut MpServices,
+        number_of_processors:          &mut usize,
+        number_of_enabled_processors:  &mut usize) -> RawStatus,
+
+    /// Returns information about a processor
+    pub get_processor_info: unsafe extern "efiapi" fn(
+        this:                  *mut MpServices,
+        processor_number:      usize,
+        processor_info_buffer: &mut ProcessorInformation) -> RawStatus,
+
+    /// Runs a function on all enabled application processors
+    pub startup_all_aps: unsafe extern "efiapi" fn(
+        this:               *mut MpServices,
+        procedure:          ApProcedure,
+        single_thread:      bool,
+        wait_event:         Event,
+        timeout_in_us:      usize,
+        procedure_argument: *mut c_void,
+        failed_cpu_list:    *mut *mut usize) -> RawStatus,
+
+    /// Runs a function on a single application processor. If `wait_event` is
+    /// given, this returns right away and the event is signaled once the
+    /// function returns.
+    pub startup_this_ap: unsafe extern "efiapi" fn(
+        this:               *mut MpServices,
+        procedure:          ApProcedure,
+        processor_number:   usize,
+        wait_event:         Event,
+        timeout_in_us:      usize,
+        procedure_argument: *mut c_void,
+        finished:           *mut u8) -> RawStatus,
+
+    /// Switches the role of the BSP to another processor
+    pub switch_bsp: unsafe extern "efiapi" fn(
+        this:             *mut MpServices,
+        processor_number: usize,
+        enable_old_bsp:   bool) -> RawStatus,
+
+    /// Enables or disables an application processor
+    pub enable_disable_ap: unsafe extern "efiapi" fn(
+        this:             *mut MpServices,
+        processor_number: usize,
+        enable_ap:        bool,
+        health_flag:      *const u32) -> RawStatus,
+
+    /// Returns the number of the processor calling this
+    pub who_am_i: unsafe extern "efiapi" fn(
+        this:             *mut MpServices,
+        processor_number: &mut usize) -> RawStatus,
+}
+
+unsafe impl Protocol for MpServices {
+    const GUID: Guid = Guid::new(0x3fdda605, 0xa76e, 0x4f46,
+        [0xad, 0x29, 0x12, 0xf4, 0x53, 0x1b, 0x3d, 0x08]);
+}
+
+impl MpServices {
+    /// Returns the number of logical processors and the number of enabled
+    /// logical processors
+    pub fn number_of_processors(&mut self)
+            -> Result<(usize, usize), status::Error> {
+        let mut total = 0;
+        let mut enabled = 0;
+        unsafe {
+            (self.get_number_of_processors)(self, &mut total, &mut enabled)
+        }.result()?;
+        Ok((total, enabled))
+    }
+
+    /// Returns information about the processor `processor`
+    pub fn processor_info(&mut self, processor: usize)
+            -> Result<ProcessorInformation, status::Error> {
+        let mut info = ProcessorInformation::default();
+        unsafe { (self.get_processor_info)(self, processor, &mut info) }
+            .result()?;
+        Ok(info)
+    }
+
+    /// Start `procedure(argument)` on the processor `processor` without
+    /// waiting for it to return. `event` is signaled once it does.
+    ///
+    /// # Safety
+    ///
+    /// `procedure` runs on a firmware stack and must not call into the
+    /// firmware. `argument` must be sound to hand to it on another processor,
+    /// and `event` must be a valid event that's never closed, as the firmware
+    /// keeps using it.
+    pub unsafe fn startup_this_ap(&mut self, procedure: ApProcedure,
+                                  processor: usize, event: Event,
+                                  argument: *mut c_void)
+            -> Result<(), status::Error> {
+        unsafe {
+            (self.startup_this_ap)(self, procedure, processor, event, 0,
+                                   argument, core::ptr::null_mut())
+        }.result()?;
+        Ok(())
+    }
+
+    /// Returns the number of the processor calling this
+    pub fn who_am_i(&mut self) -> Result<usize, status::Error> {
+        let mut processor = 0;
+        unsafe { (self.who_am_i)(self, &mut processor) }.result()?;
+        Ok(processor)
+    }
+}
@@ -0,0 +1,409 @@
+//! EFI_PXE_BASE_CODE_PROTOCOL, used to download files from the boot server
+//! over TFTP
+
+use core::fmt;
+use core::ffi::c_void;
+use crate::efi::*;
+
+/// Get the size of a file over TFTP
+pub const TFTP_GET_FILE_SIZE: u32 = 1;
+
+/// Read a file over TFTP
+pub const TFTP_READ_FILE: u32 = 2;
+
+/// Write a file over TFTP
+pub const TFTP_WRITE_FILE: u32 = 3;
+
+/// Read a directory over TFTP
+pub const TFTP_READ_DIRECTORY: u32 = 4;
+
+/// Maximum length of a file name we can download, in bytes
+const MAX_NAME: usize = 256;
+
+/// Offset of the boot server address (`siaddr`) in a DHCPv4 packet
+const DHCP_SIADDR: usize = 20;
+
+/// Errors returned by the PXE routines
+#[derive(Debug)]
+pub enum Error {
+    /// There's no network interface with PXE support
+    NoPxe(status::Error),
+
+    /// Couldn't start the PXE base code
+    StartFailed(status::Error),
+
+    /// Couldn't get an address through DHCP
+    DhcpFailed(status::Error),
+
+    /// DHCP didn't tell us about a boot server
+    NoBootServer,
+
+    /// The file name is too long or isn't ASCII
+    InvalidName,
+
+    /// Couldn't get the size of the file
+    GetFileSizeFailed(status::Error),
+
+    /// Couldn't allocate memory for the file
+    AllocationFailed(status::Error),
+
+    /// Couldn't download the file
+    ReadFailed(status::Error),
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[repr(C, align(4))]
+/// An IPv4 or IPv6 address. IPv4 addresses only use the first 4 bytes.
+pub struct IpAddress(pub [u8; 16]);
+
+impl IpAddress {
+    /// Returns the IPv4 address `addr`
+    pub fn v4(addr: [u8; 4]) -> Self {
+        let mut ip = Self::default();
+        ip.0[..4].copy_from_slice(&addr);
+        ip
+    }
+}
+
+impl fmt::Display for IpAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+#[derive(Copy, Clone)]
+#[repr(C, align(4))]
+/// A raw DHCP or PXE packet
+pub struct Packet(pub [u8; 1472]);
+
+impl Packet {
+    /// Returns the boot server address (`siaddr`) of a DHCPv4 packet, if it
+    /// has one
+    pub fn siaddr(&self) -> Option<IpAddress> {
+        let addr: [u8; 4] =
+            self.0[DHCP_SIADDR..DHCP_SIADDR + 4].try_into().unwrap();
+        (addr != [0; 4]).then(|| IpAddress::v4(addr))
+    }
+}
+
+impl fmt::Debug for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Packet")
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// State of the PXE base code. Only the fields we use are described here;
+/// more state follows, so this is only ever accessed through the pointer the
+/// firmware hands out. Its BOOLEANs are raw bytes, as the firmware may put
+/// anything in them.
+pub struct PxeBaseCodeMode {
+    /// Whether the base code has been started
+    pub started: u8,
+
+    /// Whether IPv6 is available
+    pub ipv6_available: u8,
+
+    /// Whether IPv6 is supported
+    pub ipv6_supported: u8,
+
+    /// Whether IPv6 is being used
+    pub using_ipv6: u8,
+
+    /// Whether BIS is supported
+    pub bis_supported: u8,
+
+    /// Whether BIS was detected
+    pub bis_detected: u8,
+
+    /// Whether ARP is done automatically
+    pub auto_arp: u8,
+
+    /// Whether the system GUID is sent in DHCP packets
+    pub send_guid: u8,
+
+    /// Whether `dhcp_discover` is valid
+    pub dhcp_discover_valid: u8,
+
+    /// Whether `dhcp_ack` is valid
+    pub dhcp_ack_received: u8,
+
+    /// Whether `proxy_offer` is valid
+    pub proxy_offer_received: u8,
+
+    /// Whether the PXE discover packet is valid
+    pub pxe_discover_valid: u8,
+
+    /// Whether the PXE reply packet is valid
+    pub pxe_reply_received: u8,
+
+    /// Whether the PXE BIS reply packet is valid
+    pub pxe_bis_reply_received: u8,
+
+    /// Whether an ICMP error was received
+    pub icmp_error_received: u8,
+
+    /// Whether a TFTP error was received
+    pub tftp_error_received: u8,
+
+    /// Whether callbacks are made
+    pub make_callbacks: u8,
+
+    /// Time to live of outgoing packets
+    pub ttl: u8,
+
+    /// Type of service of outgoing packets
+    pub tos: u8,
+
+    /// Our address
+    pub station_ip: IpAddress,
+
+    /// Our subnet mask
+    pub subnet_mask: IpAddress,
+
+    /// The DHCP discover packet we sent
+    pub dhcp_discover: Packet,
+
+    /// The DHCP acknowledgement we received
+    pub dhcp_ack: Packet,
+
+    /// The proxy DHCP offer we received
+    pub proxy_offer: Packet,
+}
+
+impl PxeBaseCodeMode {
+    /// Returns whether the base code has been started
+    pub fn started(&self) -> bool {
+        self.started != 0
+    }
+
+    /// Returns the address of the server we can download files from
+    pub fn boot_server(&self) -> Option<IpAddress> {
+        // A proxy DHCP server knows better than the regular one
+        if self.proxy_offer_received != 0 &&
+                let Some(ip) = self.proxy_offer.siaddr() {
+            return Some(ip);
+        }
+
+        (self.dhcp_ack_received != 0).then(|| self.dhcp_ack.siaddr()).flatten()
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// Provides the PXE services: DHCP, TFTP and UDP over a network interface
+pub struct PxeBaseCode {
+    /// Revision of the protocol
+    pub revision: u64,
+
+    /// Enables the use of the PXE base code
+    pub start: unsafe extern "efiapi" fn(
+        this:     *mut PxeBaseCode,
+        use_ipv6: bool) -> RawStatus,
+
+    /// Disables the use of the PXE base code
+    pub stop: unsafe extern "efiapi" fn(this: *mut PxeBaseCode) -> RawStatus,
+
+    /// Acquires an address through DHCP
+    pub dhcp: unsafe extern "efiapi" fn(
+        this:        *mut PxeBaseCode,
+        sort_offers: bool) -> RawStatus,
+
+    /// Attempts to complete a PXE boot server discovery sequence
+    pub discover: unsafe extern "efiapi" fn(
+        this:    *mut PxeBaseCode,
+        ty:      u16,
+        layer:   *mut u16,
+        use_bis: bool,
+        info:    *const c_void) -> RawStatus,
+
+    /// Performs a TFTP or MTFTP operation
+    pub mtftp: unsafe extern "efiapi" fn(
+        this:            *mut PxeBaseCode,
+        operation:       u32,
+        buffer_ptr:      *mut c_void,
+        overwrite:       bool,
+        buffer_size:     &mut u64,
+        block_size:      *const usize,
+        server_ip:       &IpAddress,
+        filename:        *const u8,
+        info:            *const c_void,
+        dont_use_buffer: bool) -> RawStatus,
+
+    /// Writes a UDP packet to the network interface
+    pub udp_write: unsafe extern "efiapi" fn(
+        this:        *mut PxeBaseCode,
+        op_flags:    u16,
+        dest_ip:     *const IpAddress,
+        dest_port:   *const u16,
+        gateway_ip:  *const IpAddress,
+        src_ip:      *const IpAddress,
+        src_port:    *mut u16,
+        header_size: *const usize,
+        header_ptr:  *const c_void,
+        buffer_size: *const usize,
+        buffer_ptr:  *const c_void) -> RawStatus,
+
+    /// Reads a UDP packet from the network interface
+    pub udp_read: unsafe extern "efiapi" fn(
+        this:        *mut PxeBaseCode,
+        op_flags:    u16,
+        dest_ip:     *mut IpAddress,
+        dest_port:   *mut u16,
+        src_ip:      *mut IpAddress,
+        src_port:    *mut u16,
+        header_size: *const usize,
+        header_ptr:  *mut c_void,
+        buffer_size: *mut usize,
+        buffer_ptr:  *mut c_void) -> RawStatus,
+
+    /// Updates the IP receive filters
+    pub set_ip_filter: unsafe extern "efiapi" fn(
+        this:       *mut PxeBaseCode,
+        new_filter: *const c_void) -> RawStatus,
+
+    /// Uses ARP to resolve an IP address to a MAC address
+    pub arp: unsafe extern "efiapi" fn(
+        this:     *mut PxeBaseCode,
+        ip_addr:  *const IpAddress,
+        mac_addr: *mut c_void) -> RawStatus,
+
+    /// Updates the parameters that affect the operation of the base code
+    pub set_parameters: unsafe extern "efiapi" fn(
+        this:              *mut PxeBaseCode,
+        new_auto_arp:      *const bool,
+        new_send_guid:     *const bool,
+        new_ttl:           *const u8,
+        new_tos:           *const u8,
+        new_make_callback: *const bool) -> RawStatus,
+
+    /// Updates our address and subnet mask
+    pub set_station_ip: unsafe extern "efiapi" fn(
+        this:            *mut PxeBaseCode,
+        new_station_ip:  *const IpAddress,
+        new_subnet_mask: *const IpAddress) -> RawStatus,
+
+    /// Updates the contents of the cached DHCP and discover packets
+    pub set_packets: unsafe extern "efiapi" fn(
+        this:                       *mut PxeBaseCode,
+        new_dhcp_discover_valid:    *const bool,
+        new_dhcp_ack_received:      *const bool,
+        new_proxy_offer_received:   *const bool,
+        new_pxe_discover_valid:     *const bool,
+        new_pxe_reply_received:     *const bool,
+        new_pxe_bis_reply_received: *const bool,
+        new_dhcp_discover:          *const Packet,
+        new_dhcp_ack:               *const Packet,
+        new_proxy_offer:            *const Packet,
+        new_pxe_discover:           *const Packet,
+        new_pxe_reply:              *const Packet,
+        new_pxe_bis_reply:          *const Packet) -> RawStatus,
+
+    /// State of the base code
+    pub mode: *const PxeBaseCodeMode,
+}
+
+unsafe impl Protocol for PxeBaseCode {
+    const GUID: Guid = Guid::new(0x03c4e603, 0xac28, 0x11d3,
+        [0x9a, 0x2d, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]);
+}
+
+impl PxeBaseCode {
+    /// Returns the state of the base code
+    pub fn mode(&self) -> &PxeBaseCodeMode {
+        unsafe { &*self.mode }
+    }
+
+    /// Perform the TFTP `operation` on the file `filename` on `server`.
+    ///
+    /// `filename` must be a null-terminated ASCII string. `size` is the size
+    /// of `buffer` on input and the size of the file on output.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be writable for `size` bytes, or null for operations
+    /// that don't transfer the file.
+    pub unsafe fn mtftp(&mut self, operation: u32, buffer: *mut u8,
+                        size: &mut u64, server: &IpAddress, filename: &[u8])
+            -> Result<(), status::Error> {
+        // Make sure the name is null-terminated
+        if filename.last() != Some(&0) {
+            return Err(status::Error::InvalidParameter);
+        }
+
+        unsafe {
+            (self.mtftp)(self, operation, buffer as *mut c_void, false, size,
+                         core::ptr::null(), server, filename.as_ptr(),
+                         core::ptr::null(), false)
+        }.result()?;
+        Ok(())
+    }
+}
+
+/// Download the file `name` from the boot server into `LoaderData` memory,
+/// which survives `exit_boot_services()`.
+///
+/// The network interface we were booted from is used if there is one,
+/// otherwise any interface with PXE support is brought up through DHCP.
+///
+/// # Safety
+///
+/// `img_handle` must be the handle of our image, and the boot services must
+/// not have been exited.
+pub unsafe fn download(boot_svc: &BootServices, img_handle: Handle, name: &str)
+        -> Result<&'static [u8], Error> {
+    // Prefer the interface we were booted from
+    let pxe = unsafe {
+        let image = boot_svc.handle_protocol::<LoadedImage>(img_handle)
+            .map_err(Error::NoPxe)?;
+        &mut *boot_svc.handle_protocol::<PxeBaseCode>((*image).device_handle)
+            .or_else(|_| boot_svc.locate_protocol::<PxeBaseCode>())
+            .map_err(Error::NoPxe)?
+    };
+
+    // Bring up the network if nobody did yet
+    if !pxe.mode().started() {
+        unsafe { (pxe.start)(pxe, false) }.result()
+            .map_err(Error::StartFailed)?;
+        unsafe { (pxe.dhcp)(pxe, true) }.result()
+            .map_err(Error::DhcpFailed)?;
+    }
+    let server = pxe.mode().boot_server().ok_or(Error::NoBootServer)?;
+
+    // TFTP wants a null-terminated ASCII name
+    let mut filename = [0u8; MAX_NAME + 1];
+    if name.len() > MAX_NAME || !name.is_ascii() || name.contains('\0') {
+        return Err(Error::InvalidName);
+    }
+    filename[..name.len()].copy_from_slice(name.as_bytes());
+    let filename = &filename[..=name.len()];
+
+    // Get the size of the file
+    let mut size = 0;
+    unsafe {
+        pxe.mtftp(TFTP_GET_FILE_SIZE, core::ptr::null_mut(), &mut size,
+                  &server, filename)
+    }.map_err(Error::GetFileSizeFailed)?;
+    let size = size as usize;
+
+    // Allocate the memory for the file, at least one page so even empty files
+    // get a valid address
+    let pages = size.div_ceil(4096).max(1);
+    let buffer = boot_svc.allocate_pages(AllocateType::AnyPages,
+                                         MemoryType::LoaderData, pages, 0)
+        .map_err(Error::AllocationFailed)? as *mut u8;
+
+    // Download the file, giving the memory back if that fails
+    let mut read = size as u64;
+    let status = unsafe {
+        pxe.mtftp(TFTP_READ_FILE, buffer, &mut read, &server, filename)
+    };
+    if let Err(err) = status {
+        let _ = unsafe { boot_svc.free_pages(buffer as u64, pages) };
+        return Err(Error::ReadFailed(err));
+    }
+
+    let read = (read as usize).min(size);
+    Ok(unsafe { core::slice::from_raw_parts(buffer, read) })
+}
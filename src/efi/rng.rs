@@ -0,0 +1,38 @@
+//! EFI_RNG_PROTOCOL, the firmware's source of entropy
+
+use crate::efi::*;
+
+#[derive(Debug)]
+#[repr(C)]
+/// Provides random numbers from the platform's entropy sources
+pub struct Rng {
+    /// Returns the RNG algorithms supported by the driver
+    pub get_info: unsafe extern "efiapi" fn(
+        this:                    *mut Rng,
+        rng_algorithm_list_size: &mut usize,
+        rng_algorithm_list:      *mut Guid) -> RawStatus,
+
+    /// Fills `rng_value` with random bytes, generated by `rng_algorithm` or
+    /// the default algorithm if it's null
+    pub get_rng: unsafe extern "efiapi" fn(
+        this:             *mut Rng,
+        rng_algorithm:    *const Guid,
+        rng_value_length: usize,
+        rng_value:        *mut u8) -> RawStatus,
+}
+
+unsafe impl Protocol for Rng {
+    const GUID: Guid = Guid::new(0x3152bca5, 0xeade, 0x433d,
+        [0x86, 0x2e, 0xc0, 0x1c, 0xdc, 0x29, 0x1f, 0x44]);
+}
+
+impl Rng {
+    /// Fill `buf` with random bytes from the default algorithm
+    pub fn get_rng(&mut self, buf: &mut [u8]) -> Result<(), status::Error> {
+        unsafe {
+            (self.get_rng)(self, core::ptr::null(), buf.len(),
+                           buf.as_mut_ptr())
+        }.result()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,332 @@
+//! EFI Runtime Services
+//!
+//! Unlike the boot services, these stay available after `exit_boot_services()`
+//! is called. We never call `set_virtual_address_map()` ourselves, so the
+//! firmware keeps running with the 1:1 mapping it has set up.
+
+use core::fmt;
+use core::ffi::c_void;
+use crate::efi::*;
+use crate::spinlock::SpinLock;
+
+/// Runtime services are not reentrant; every call made through the wrappers
+/// in this module is serialized by this lock.
+static RUNTIME_LOCK: SpinLock<()> = SpinLock::new(());
+
+/// The variable is stored in non-volatile storage
+pub const VARIABLE_NON_VOLATILE: u32 = 0x01;
+
+/// The variable is accessible while the boot services are available
+pub const VARIABLE_BOOTSERVICE_ACCESS: u32 = 0x02;
+
+/// The variable is accessible after `exit_boot_services()` is called
+pub const VARIABLE_RUNTIME_ACCESS: u32 = 0x04;
+
+/// The variable is a hardware error record
+pub const VARIABLE_HARDWARE_ERROR_RECORD: u32 = 0x08;
+
+/// The variable is protected by a time-based authentication descriptor
+pub const VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS: u32 = 0x20;
+
+/// Writes to the variable append to the existing data
+pub const VARIABLE_APPEND_WRITE: u32 = 0x40;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[repr(C)]
+/// Current time as returned by the firmware
+pub struct Time {
+    /// 1900 - 9999
+    pub year: u16,
+
+    /// 1 - 12
+    pub month: u8,
+
+    /// 1 - 31
+    pub day: u8,
+
+    /// 0 - 23
+    pub hour: u8,
+
+    /// 0 - 59
+    pub minute: u8,
+
+    /// 0 - 59
+    pub second: u8,
+
+    /// Padding; must be 0
+    _pad1: u8,
+
+    /// 0 - 999,999,999
+    pub nanosecond: u32,
+
+    /// Offset from UTC in minutes (-1440 - 1440), or 2047 if unspecified
+    pub time_zone: i16,
+
+    /// Daylight saving time information
+    pub daylight: u8,
+
+    /// Padding; must be 0
+    _pad2: u8,
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+               self.year, self.month, self.day,
+               self.hour, self.minute, self.second)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+/// Capabilities of the real time clock
+pub struct TimeCapabilities {
+    /// Resolution of the clock in counts per second
+    pub resolution: u32,
+
+    /// Accuracy of the clock in an error rate of 1E-6 parts per million
+    pub accuracy: u32,
+
+    /// Raw BOOLEAN whether a time set operation clears the time below the
+    /// resolution level; see [`Self::sets_to_zero()`]
+    pub sets_to_zero: u8,
+}
+
+impl TimeCapabilities {
+    /// Returns whether a time set operation clears the time below the
+    /// resolution level
+    pub fn sets_to_zero(&self) -> bool {
+        self.sets_to_zero != 0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u32)]
+/// Kind of reset done by `reset_system()`
+pub enum ResetType {
+    /// Reset all circuitry within the system to its initial state
+    Cold,
+
+    /// Reset the processors while keeping the memory contents
+    Warm,
+
+    /// Put the system into a power state equivalent to ACPI G2/S5 or G3
+    Shutdown,
+
+    /// A platform specific reset, identified by a GUID in the reset data
+    PlatformSpecific,
+}
+
+impl TryFrom<u32> for ResetType {
+    type Error = u32;
+
+    fn try_from(val: u32) -> Result<ResetType, u32> {
+        match val {
+            0 => Ok(ResetType::Cold),
+            1 => Ok(ResetType::Warm),
+            2 => Ok(ResetType::Shutdown),
+            3 => Ok(ResetType::PlatformSpecific),
+            _ => Err(val),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// Header of a capsule passed to `update_capsule()`
+pub struct CapsuleHeader {
+    /// GUID identifying the capsule
+    pub capsule_guid: Guid,
+
+    /// Size of the header
+    pub header_size: u32,
+
+    /// Capsule flags
+    pub flags: u32,
+
+    /// Size of the capsule, including the header
+    pub capsule_image_size: u32,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// The EFI_RUNTIME_SERVICES table
+pub struct RuntimeServices {
+    /// The table header for this struct
+    pub hdr: TableHeader,
+
+    /// Returns the current time and date and the time-keeping capabilities of
+    /// the hardware platform
+    pub get_time: unsafe extern "efiapi" fn(
+        time:         &mut Time,
+        capabilities: *mut TimeCapabilities) -> RawStatus,
+
+    /// Sets the current local time and date
+    pub set_time: unsafe extern "efiapi" fn(time: &Time) -> RawStatus,
+
+    /// Returns the current wakeup alarm clock setting, with raw BOOLEANs
+    /// whether it's enabled and pending
+    pub get_wakeup_time: unsafe extern "efiapi" fn(
+        enabled: &mut u8,
+        pending: &mut u8,
+        time:    &mut Time) -> RawStatus,
+
+    /// Sets the system wakeup alarm clock time
+    pub set_wakeup_time: unsafe extern "efiapi" fn(
+        enable: bool,
+        time:   *const Time) -> RawStatus,
+
+    /// Changes the runtime addressing mode of EFI firmware from physical to
+    /// virtual
+    pub set_virtual_address_map: unsafe extern "efiapi" fn(
+        memory_map_size:    usize,
+        descriptor_size:    usize,
+        descriptor_version: u32,
+        virtual_map:        *const MemoryDescriptor) -> RawStatus,
+
+    /// Determines the new virtual address that is to be used on subsequent
+    /// memory accesses
+    pub convert_pointer: unsafe extern "efiapi" fn(
+        debug_disposition: usize,
+        address:           &mut *const c_void) -> RawStatus,
+
+    /// Returns the value of a variable
+    pub get_variable: unsafe extern "efiapi" fn(
+        variable_name: *const u16,
+        vendor_guid:   *const Guid,
+        attributes:    *mut u32,
+        data_size:     &mut usize,
+        data:          *mut c_void) -> RawStatus,
+
+    /// Enumerates the current variable names
+    pub get_next_variable_name: unsafe extern "efiapi" fn(
+        variable_name_size: &mut usize,
+        variable_name:      *mut u16,
+        vendor_guid:        *mut Guid) -> RawStatus,
+
+    /// Sets the value of a variable
+    pub set_variable: unsafe extern "efiapi" fn(
+        variable_name: *const u16,
+        vendor_guid:   *const Guid,
+        attributes:    u32,
+        data_size:     usize,
+        data:          *const c_void) -> RawStatus,
+
+    /// Returns the next high 32 bits of the platform's monotonic counter
+    pub get_next_high_monotonic_count: unsafe extern "efiapi" fn(
+        high_count: &mut u32) -> RawStatus,
+
+    /// Resets the entire platform. Only returns if the reset can't be done.
+    pub reset_system: unsafe extern "efiapi" fn(
+        reset_type:   ResetType,
+        reset_status: RawStatus,
+        data_size:    usize,
+        reset_data:   *const c_void) -> RawStatus,
+
+    /// Passes capsules to the firmware with both virtual and physical mapping
+    pub update_capsule: unsafe extern "efiapi" fn(
+        capsule_header_array: *const *const CapsuleHeader,
+        capsule_count:        usize,
+        scatter_gather_list:  PhysAddr) -> RawStatus,
+
+    /// Returns if the capsule can be supported via `update_capsule()`, and
+    /// the raw [`ResetType`] it needs
+    pub query_capsule_capabilities: unsafe extern "efiapi" fn(
+        capsule_header_array: *const *const CapsuleHeader,
+        capsule_count:        usize,
+        maximum_capsule_size: &mut u64,
+        reset_type:           &mut u32) -> RawStatus,
+
+    /// Returns information about the EFI variables
+    pub query_variable_info: unsafe extern "efiapi" fn(
+        attributes:                      u32,
+        maximum_variable_storage_size:   &mut u64,
+        remaining_variable_storage_size: &mut u64,
+        maximum_variable_size:           &mut u64) -> RawStatus,
+}
+
+impl RuntimeServices {
+    /// Returns the current time as reported by the firmware
+    pub fn get_time(&self) -> Result<Time, status::Error> {
+        let _lock = RUNTIME_LOCK.lock();
+        let mut time = Time::default();
+        unsafe { (self.get_time)(&mut time, core::ptr::null_mut()) }
+            .result()?;
+        Ok(time)
+    }
+
+    /// Read the variable `name` of vendor `guid` into `data`.
+    ///
+    /// `name` must be a null-terminated UCS-2 string.
+    /// Returns the size of the variable and its attributes.
+    pub fn get_variable(&self, name: &[u16], guid: &Guid, data: &mut [u8])
+            -> Result<(usize, u32), status::Error> {
+        // Make sure the name is null-terminated
+        if name.last() != Some(&0) {
+            return Err(status::Error::InvalidParameter);
+        }
+
+        let _lock = RUNTIME_LOCK.lock();
+        let mut attributes = 0;
+        let mut size = data.len();
+        unsafe {
+            (self.get_variable)(name.as_ptr(), guid, &mut attributes,
+                                &mut size, data.as_mut_ptr() as *mut c_void)
+        }.result()?;
+        Ok((size, attributes))
+    }
+
+    /// Set the variable `name` of vendor `guid` to `data` with `attributes`.
+    /// An empty `data` deletes the variable.
+    ///
+    /// `name` must be a null-terminated UCS-2 string.
+    pub fn set_variable(&self, name: &[u16], guid: &Guid, attributes: u32,
+                        data: &[u8]) -> Result<(), status::Error> {
+        // Make sure the name is null-terminated
+        if name.last() != Some(&0) {
+            return Err(status::Error::InvalidParameter);
+        }
+
+        let _lock = RUNTIME_LOCK.lock();
+        unsafe {
+            (self.set_variable)(name.as_ptr(), guid, attributes, data.len(),
+                                data.as_ptr() as *const c_void)
+        }.result()?;
+        Ok(())
+    }
+
+    /// Switch the firmware to the virtual addresses set in the
+    /// `virt_addr` fields of the descriptors in `map`.
+    ///
+    /// # Safety
+    ///
+    /// Can only be called once, after `exit_boot_services()`. `map` must
+    /// point to `size` bytes of descriptors `desc_size` bytes apart, whose
+    /// virtual addresses are mapped. Every pointer obtained from the firmware
+    /// is invalid afterwards unless it's converted.
+    pub unsafe fn set_virtual_address_map(&self, map: *const MemoryDescriptor,
+                                          size: usize, desc_size: usize,
+                                          desc_version: u32)
+            -> Result<(), status::Error> {
+        let _lock = RUNTIME_LOCK.lock();
+        unsafe {
+            (self.set_virtual_address_map)(size, desc_size, desc_version, map)
+        }.result()?;
+        Ok(())
+    }
+
+    /// Reset the platform with `reset_type`, reporting `status` as the reason.
+    ///
+    /// Only returns if the firmware couldn't do the reset, e.g. because it
+    /// doesn't support `reset_type`. Returns why.
+    pub fn reset_system(&self, reset_type: ResetType, status: RawStatus)
+            -> status::Error {
+        let _lock = RUNTIME_LOCK.lock();
+        let ret = unsafe {
+            (self.reset_system)(reset_type, status, 0, core::ptr::null())
+        };
+
+        // Returning at all is a failure, even if the firmware claims success
+        ret.result().err().unwrap_or(status::Error::Unsupported)
+    }
+}
@@ -14,22 +14,58 @@ pub enum Status {
     Error(Error),
 }
 
+impl Status {
+    /// Convert the status into a `Result`, treating warnings as success
+    pub fn result(self) -> Result<Option<Warning>, Error> {
+        match self {
+            Status::Success    => Ok(None),
+            Status::Warning(w) => Ok(Some(w)),
+            Status::Error(e)   => Err(e),
+        }
+    }
+}
+
 impl From<usize> for Status {
     fn from(val: usize) -> Status {
-        // Sign extend the code to make it not tied to a specific bitness
-        let val = val as i32 as i64 as u64;
-        let code = (val & !(1 << 63)) as usize;
+        // The highest bit marks errors, regardless of the bitness
+        let error_bit = 1 << (usize::BITS - 1);
+        let code = val & !error_bit;
 
         match val {
-            0 => Self::Success,
-            0x0000000000000001..0x8000000000000000 =>
-                Self::Warning(Warning::from(code)),
-            0x8000000000000000..=u64::MAX =>
-                Self::Error(Error::from(code)),
+            0                          => Self::Success,
+            _ if val & error_bit == 0  => Self::Warning(Warning::from(code)),
+            _                          => Self::Error(Error::from(code)),
         }
     }
 }
 
+impl From<RawStatus> for Status {
+    fn from(val: RawStatus) -> Status {
+        Status::from(val.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(transparent)]
+/// EFI_STATUS as it is passed to and returned from EFI routines. Convert it to
+/// [`Status`] to inspect it.
+pub struct RawStatus(pub usize);
+
+impl RawStatus {
+    /// The raw success status
+    pub const SUCCESS: RawStatus = RawStatus(0);
+
+    /// Returns the parsed status
+    pub fn status(self) -> Status {
+        Status::from(self)
+    }
+
+    /// Convert the status into a `Result`, treating warnings as success
+    pub fn result(self) -> Result<Option<Warning>, Error> {
+        self.status().result()
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 #[repr(usize)]
 /// Warning codes returned by EFI functions
@@ -0,0 +1,169 @@
+//! Validation of the standard EFI tables
+//!
+//! Every standard table starts with a [`TableHeader`] carrying a signature and
+//! a CRC32 of the table. Checking both before following any of the pointers in
+//! the tables turns broken firmware into a clear error instead of a jump
+//! through a garbage pointer.
+
+use core::fmt;
+use core::mem::size_of;
+use crate::efi::*;
+
+/// Errors returned by the table validation
+#[derive(Debug)]
+pub enum Error {
+    /// The table pointer is null
+    NullTable,
+
+    /// The table has a signature different from the one it should have
+    WrongSignature { expected: u64, found: u64 },
+
+    /// The header claims the table is smaller than the structure we use to
+    /// access it
+    WrongHeaderSize(u32),
+
+    /// The CRC32 of the table doesn't match the one in its header
+    WrongCrc { expected: u32, computed: u32 },
+}
+
+/// A standard EFI table beginning with a [`TableHeader`]
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` structures starting with a
+/// [`TableHeader`], and `SIGNATURE` must be the one of the table they
+/// describe.
+pub unsafe trait Table {
+    /// Signature of the table
+    const SIGNATURE: u64;
+}
+
+unsafe impl Table for SystemTable {
+    const SIGNATURE: u64 = 0x5453595320494249; // "IBI SYST"
+}
+
+unsafe impl Table for BootServices {
+    const SIGNATURE: u64 = 0x56524553544f4f42; // "BOOTSERV"
+}
+
+unsafe impl Table for RuntimeServices {
+    const SIGNATURE: u64 = 0x56524553544e5552; // "RUNTSERV"
+}
+
+/// A revision of the EFI specification
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Revision {
+    /// The major revision
+    pub major: u16,
+
+    /// The minor revision, e.g. 31 for x.3.1
+    pub minor: u16,
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor / 10)?;
+        if !self.minor.is_multiple_of(10) {
+            write!(f, ".{}", self.minor % 10)?;
+        }
+        Ok(())
+    }
+}
+
+impl TableHeader {
+    /// Returns the revision of the specification the table conforms to
+    pub fn revision(&self) -> Revision {
+        Revision {
+            major: (self.revision >> 16) as u16,
+            minor: self.revision as u16,
+        }
+    }
+}
+
+/// Validate the signature, size and CRC32 of the table at `table`
+///
+/// # Safety
+///
+/// `table` must be null or point to readable memory holding at least a
+/// [`TableHeader`], and as many bytes as the header claims. The table must
+/// stay where it is for as long as the returned reference is used.
+pub unsafe fn validate<T: Table>(table: *const T)
+        -> Result<&'static T, Error> {
+    if table.is_null() { return Err(Error::NullTable); }
+    let hdr = unsafe { &*(table as *const TableHeader) };
+
+    // Check the signature first; it's the only thing we can trust to tell us
+    // this is the table we think it is
+    if hdr.signature != T::SIGNATURE {
+        return Err(Error::WrongSignature {
+            expected: T::SIGNATURE,
+            found:    hdr.signature,
+        });
+    }
+
+    // Make sure the table is as large as the structure we access it through
+    if (hdr.header_size as usize) < size_of::<T>() {
+        return Err(Error::WrongHeaderSize(hdr.header_size));
+    }
+
+    // Compute the CRC32 of the table with the CRC32 field zeroed
+    let bytes = unsafe {
+        core::slice::from_raw_parts(table as *const u8,
+                                    hdr.header_size as usize)
+    };
+    let crc_offset = core::mem::offset_of!(TableHeader, crc32);
+    let computed = crc32(&[
+        &bytes[..crc_offset],
+        &[0; 4],
+        &bytes[crc_offset + 4..],
+    ]);
+
+    if computed != hdr.crc32 {
+        return Err(Error::WrongCrc { expected: hdr.crc32, computed });
+    }
+
+    Ok(unsafe { &*table })
+}
+
+/// Validate the system table at `sys_table` and the boot and runtime services
+/// tables it points to.
+///
+/// Returns the revision of the specification the firmware conforms to.
+///
+/// # Safety
+///
+/// See [`validate()`]; the same applies to the services tables.
+pub unsafe fn validate_system_table(sys_table: *const SystemTable)
+        -> Result<Revision, Error> {
+    let sys_table = unsafe { validate(sys_table)? };
+    unsafe {
+        validate(sys_table.boot_svc)?;
+        validate(sys_table.runtime_svc)?;
+    }
+    Ok(sys_table.hdr.revision())
+}
+
+/// Lookup table for the CRC32 computation
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+};
+
+/// Compute the CRC32 (as used by EFI, i.e. the IEEE 802.3 one) of the
+/// concatenation of `chunks`
+pub fn crc32(chunks: &[&[u8]]) -> u32 {
+    !chunks.iter().flat_map(|chunk| chunk.iter()).fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
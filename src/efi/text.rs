@@ -0,0 +1,138 @@
+//! EFI_SIMPLE_TEXT_OUTPUT_PROTOCOL, the firmware's text console
+
+use crate::efi::*;
+
+/// Number of UCS-2 characters converted at a time by
+/// [`SimpleTextOutput::output()`], including the terminator
+const OUTPUT_CHUNK: usize = 128;
+
+#[derive(Debug)]
+#[repr(C)]
+/// The current mode of a text output device
+pub struct SimpleTextOutputMode {
+    /// Number of modes supported by `query_mode()` and `set_mode()`
+    pub max_mode: i32,
+
+    /// The current mode
+    pub mode: i32,
+
+    /// The current foreground and background colors
+    pub attribute: i32,
+
+    /// The cursor's column
+    pub cursor_column: i32,
+
+    /// The cursor's row
+    pub cursor_row: i32,
+
+    /// Raw BOOLEAN whether the cursor is visible
+    pub cursor_visible: u8,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+/// Controls text-based output devices, most notably `con_out` of the
+/// [`SystemTable`]
+pub struct SimpleTextOutput {
+    /// Resets the text output device hardware
+    pub reset: unsafe extern "efiapi" fn(
+        this:                  *mut SimpleTextOutput,
+        extended_verification: bool) -> RawStatus,
+
+    /// Writes a null-terminated string to the output device
+    pub output_string: unsafe extern "efiapi" fn(
+        this:   *mut SimpleTextOutput,
+        string: *const u16) -> RawStatus,
+
+    /// Verifies that all characters in a string can be output to the target
+    /// device
+    pub test_string: unsafe extern "efiapi" fn(
+        this:   *mut SimpleTextOutput,
+        string: *const u16) -> RawStatus,
+
+    /// Returns information for an available text mode that the output device
+    /// supports
+    pub query_mode: unsafe extern "efiapi" fn(
+        this:        *mut SimpleTextOutput,
+        mode_number: usize,
+        columns:     &mut usize,
+        rows:        &mut usize) -> RawStatus,
+
+    /// Sets the output device to a specified mode
+    pub set_mode: unsafe extern "efiapi" fn(
+        this:        *mut SimpleTextOutput,
+        mode_number: usize) -> RawStatus,
+
+    /// Sets the foreground and background colors of the output device
+    pub set_attribute: unsafe extern "efiapi" fn(
+        this:      *mut SimpleTextOutput,
+        attribute: usize) -> RawStatus,
+
+    /// Clears the output device's display to the current background color
+    pub clear_screen: unsafe extern "efiapi" fn(
+        this: *mut SimpleTextOutput) -> RawStatus,
+
+    /// Sets the current coordinates of the cursor position
+    pub set_cursor_position: unsafe extern "efiapi" fn(
+        this:   *mut SimpleTextOutput,
+        column: usize,
+        row:    usize) -> RawStatus,
+
+    /// Makes the cursor visible or invisible
+    pub enable_cursor: unsafe extern "efiapi" fn(
+        this:    *mut SimpleTextOutput,
+        visible: bool) -> RawStatus,
+
+    /// The current mode of the device
+    pub mode: *const SimpleTextOutputMode,
+}
+
+unsafe impl Protocol for SimpleTextOutput {
+    const GUID: Guid = Guid::new(0x387477c2, 0x69c7, 0x11d2,
+        [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]);
+}
+
+impl SimpleTextOutput {
+    /// Write `string` to the output device.
+    ///
+    /// Newlines are turned into the `\r\n` the firmware expects and characters
+    /// UCS-2 can't encode are written as `?`.
+    pub fn output(&self, string: &str) -> Result<(), status::Error> {
+        let mut buf = [0u16; OUTPUT_CHUNK];
+        let mut len = 0;
+
+        for c in string.chars() {
+            // Flush the buffer if a newline and the terminator might not fit
+            if len + 3 > buf.len() {
+                self.output_ucs2(&mut buf, len)?;
+                len = 0;
+            }
+
+            if c == '\n' {
+                buf[len] = b'\r' as u16;
+                len += 1;
+            }
+
+            // UCS-2 only covers the basic plane, without the surrogates
+            buf[len] = u16::try_from(c as u32).ok()
+                .filter(|c| !(0xD800..0xE000).contains(c))
+                .unwrap_or(b'?' as u16);
+            len += 1;
+        }
+
+        self.output_ucs2(&mut buf, len)
+    }
+
+    /// Terminate the first `len` characters of `buf` and write them out
+    fn output_ucs2(&self, buf: &mut [u16], len: usize)
+            -> Result<(), status::Error> {
+        if len == 0 { return Ok(()); }
+
+        // The firmware keeps its state behind `mode`, never in the protocol
+        // itself, so there's no need for a unique reference to call it
+        buf[len] = 0;
+        let this = self as *const Self as *mut Self;
+        unsafe { (self.output_string)(this, buf.as_ptr()) }.result()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+//! UCS-2 strings as used by the firmware
+
+use core::fmt;
+
+/// Maximum length of a null-terminated string we're willing to look for the
+/// terminator in
+const MAX_LEN: usize = 4096;
+
+/// Returns the null-terminated UCS-2 string at `ptr`, without the terminator.
+///
+/// Strings longer than [`MAX_LEN`] characters are truncated.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a readable string that's terminated or at
+/// least [`MAX_LEN`] characters long, and that's never freed.
+pub unsafe fn from_ptr(ptr: *const u16) -> &'static [u16] {
+    if ptr.is_null() { return &[]; }
+
+    let len = (0..MAX_LEN)
+        .find(|&idx| unsafe { *ptr.add(idx) } == 0)
+        .unwrap_or(MAX_LEN);
+    unsafe { core::slice::from_raw_parts(ptr, len) }
+}
+
+/// Wrapper which displays a UCS-2 string. Characters that can't be decoded
+/// are displayed as the replacement character.
+pub struct Display<'a>(pub &'a [u16]);
+
+impl fmt::Display for Display<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        char::decode_utf16(self.0.iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .try_for_each(|c| write!(f, "{}", c))
+    }
+}
+
+/// Encode `string` as a null-terminated UCS-2 string into `buf`.
+///
+/// Returns the encoded string including the terminator, or `None` if it
+/// doesn't fit or `string` has characters outside of the UCS-2 range.
+pub fn encode<'a>(string: &str, buf: &'a mut [u16]) -> Option<&'a [u16]> {
+    let mut len = 0;
+    for c in string.chars() {
+        // UCS-2 can't encode characters outside of the basic plane
+        let c = u16::try_from(c as u32).ok()?;
+        *buf.get_mut(len)? = c;
+        len += 1;
+    }
+
+    // Terminate the string
+    *buf.get_mut(len)? = 0;
+    Some(&buf[..=len])
+}
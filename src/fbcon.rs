@@ -0,0 +1,189 @@
+//! A text console on the linear framebuffer
+//!
+//! The graphics mode is selected through GOP while the boot services are still
+//! around; afterwards, the framebuffer is plain memory we draw the glyphs of
+//! the built-in [`font`] into. Glyphs are drawn at twice their height, which
+//! keeps the 8x8 font readable at modern resolutions.
+
+use crate::font;
+use crate::efi::gop::{ Framebuffer, PixelFormat };
+use crate::spinlock::SpinLock;
+
+/// The global framebuffer console.
+///
+/// This console has to be initialized by `FbCon::init()` and is a global,
+/// because the print macro doesn't have access to any arguments.
+pub static FRAMEBUFFER_CONSOLE: SpinLock<Option<FbCon>> = SpinLock::new(None);
+
+/// Width of a character cell in pixels
+const CELL_WIDTH: usize = font::WIDTH;
+
+/// Height of a character cell in pixels
+const CELL_HEIGHT: usize = font::HEIGHT * 2;
+
+/// Color of the text as (red, green, blue)
+const FOREGROUND: (u8, u8, u8) = (0xAA, 0xAA, 0xAA);
+
+/// Color of the background as (red, green, blue)
+const BACKGROUND: (u8, u8, u8) = (0x00, 0x00, 0x00);
+
+/// A text console drawing into a framebuffer. Pixels are assumed to be 32 bits
+/// wide, which is what GOP guarantees for every format but bitmasks.
+pub struct FbCon {
+    /// The framebuffer we draw into
+    fb: Framebuffer,
+
+    /// Number of character columns on the screen
+    cols: usize,
+
+    /// Number of character rows on the screen
+    rows: usize,
+
+    /// Column the next character is drawn to
+    col: usize,
+
+    /// Row the next character is drawn to
+    row: usize,
+
+    /// Pixel value of the text
+    fg: u32,
+
+    /// Pixel value of the background
+    bg: u32,
+}
+
+impl FbCon {
+    /// Initialize the framebuffer console on `fb` and clear the screen.
+    ///
+    /// Does nothing if the console is already initialized or if `fb` is too
+    /// small to hold a single character.
+    pub fn init(fb: Framebuffer) {
+        // Get the lock to the global console
+        let mut console = FRAMEBUFFER_CONSOLE.lock();
+
+        // Make sure we're not re-initializing
+        if console.is_some() { return; }
+
+        // Make sure the screen fits the framebuffer and at least a character
+        let cols = fb.width / CELL_WIDTH;
+        let rows = fb.height / CELL_HEIGHT;
+        if cols == 0 || rows == 0 || fb.width > fb.stride ||
+                fb.stride * fb.height * 4 > fb.size {
+            return;
+        }
+
+        let mut fbcon = Self {
+            fb, cols, rows,
+            col: 0,
+            row: 0,
+            fg:  Self::color(&fb, FOREGROUND),
+            bg:  Self::color(&fb, BACKGROUND),
+        };
+
+        // Start out with a blank screen
+        for y in 0..fb.height {
+            fbcon.fill_line(y, fbcon.bg);
+        }
+
+        // Save the initialized console
+        *console = Some(fbcon);
+    }
+
+    /// Returns the pixel value of the (red, green, blue) `color` in the format
+    /// of `fb`
+    fn color(fb: &Framebuffer, (red, green, blue): (u8, u8, u8)) -> u32 {
+        // Scale a color component into the bits of `mask`
+        let scale = |mask: u32, val: u8| {
+            if mask == 0 { return 0; }
+            let shift = mask.trailing_zeros();
+            let max = (mask >> shift) as u64;
+            ((val as u64 * max / 0xFF) as u32) << shift
+        };
+
+        match fb.format {
+            PixelFormat::Rgb =>
+                red as u32 | (green as u32) << 8 | (blue as u32) << 16,
+            PixelFormat::Bgr =>
+                blue as u32 | (green as u32) << 8 | (red as u32) << 16,
+            _ => scale(fb.mask.red,   red)   |
+                 scale(fb.mask.green, green) |
+                 scale(fb.mask.blue,  blue),
+        }
+    }
+
+    /// Returns a pointer to the pixel at (`x`, `y`)
+    fn pixel(&self, x: usize, y: usize) -> *mut u32 {
+        (self.fb.base as *mut u32).wrapping_add(y * self.fb.stride + x)
+    }
+
+    /// Fill the visible part of pixel line `y` with `color`
+    fn fill_line(&mut self, y: usize, color: u32) {
+        for x in 0..self.fb.width {
+            unsafe { self.pixel(x, y).write_volatile(color) };
+        }
+    }
+
+    /// Draw `byte` into the character cell at `col`, `row`
+    fn draw(&mut self, col: usize, row: usize, byte: u8) {
+        let glyph = font::glyph(byte);
+
+        for y in 0..CELL_HEIGHT {
+            // Every row of the glyph is drawn twice
+            let bits = glyph[y / 2];
+            for x in 0..CELL_WIDTH {
+                let color = if bits & (0x80 >> x) != 0 {
+                    self.fg
+                } else {
+                    self.bg
+                };
+
+                let pixel = self.pixel(col * CELL_WIDTH + x,
+                                       row * CELL_HEIGHT + y);
+                unsafe { pixel.write_volatile(color) };
+            }
+        }
+    }
+
+    /// Move everything on the screen up by one row and clear the last one
+    fn scroll(&mut self) {
+        // Move all rows but the first one up
+        let line = self.fb.stride;
+        unsafe {
+            core::ptr::copy(self.pixel(0, CELL_HEIGHT), self.pixel(0, 0),
+                            (self.rows - 1) * CELL_HEIGHT * line);
+        }
+
+        // Clear the last row
+        for y in (self.rows - 1) * CELL_HEIGHT..self.rows * CELL_HEIGHT {
+            self.fill_line(y, self.bg);
+        }
+    }
+
+    /// Move to the start of the next row, scrolling if we're at the bottom
+    fn newline(&mut self) {
+        self.col = 0;
+        self.row += 1;
+
+        if self.row == self.rows {
+            self.scroll();
+            self.row -= 1;
+        }
+    }
+
+    /// Write bytes to the screen
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            match byte {
+                b'\n' => self.newline(),
+                b'\r' => self.col = 0,
+                _ => {
+                    // Wrap lines that don't fit the screen
+                    if self.col == self.cols { self.newline(); }
+
+                    self.draw(self.col, self.row, byte);
+                    self.col += 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Files loaded into memory during boot
+//!
+//! Benchmark inputs don't have to be compiled into the image; they can be
+//! loaded before the boot services are exited and are then available to the
+//! kernel as named byte slices.
+
+use crate::spinlock::SpinLock;
+
+/// Maximum number of files that can be registered
+pub const MAX_FILES: usize = 32;
+
+/// Maximum length of a file name in bytes
+pub const MAX_NAME: usize = 64;
+
+/// The registered files
+static FILES: SpinLock<Files> = SpinLock::new(Files::new());
+
+/// Errors returned by the file registry
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// All [`MAX_FILES`] slots are taken
+    TooManyFiles,
+
+    /// The name is longer than [`MAX_NAME`] bytes
+    NameTooLong,
+}
+
+/// A file loaded into memory
+#[derive(Clone, Copy)]
+pub struct File {
+    /// Name of the file
+    name: [u8; MAX_NAME],
+
+    /// Number of bytes used in `name`
+    name_len: usize,
+
+    /// Contents of the file
+    data: &'static [u8],
+}
+
+impl File {
+    /// Returns the name of the file
+    pub fn name(&self) -> &str {
+        // Only ever created from a `&str`
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap()
+    }
+
+    /// Returns the contents of the file
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+}
+
+impl core::fmt::Debug for File {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "File({:?}, {} bytes)", self.name(), self.data.len())
+    }
+}
+
+/// The registry of loaded files
+#[derive(Debug, Clone, Copy)]
+pub struct Files {
+    /// The registered files
+    files: [Option<File>; MAX_FILES],
+}
+
+impl Files {
+    /// Returns an empty registry
+    const fn new() -> Self {
+        Self { files: [None; MAX_FILES] }
+    }
+
+    /// Returns an iterator over the registered files
+    pub fn iter(&self) -> impl Iterator<Item = &File> {
+        self.files.iter().flatten()
+    }
+}
+
+/// Register `data` under `name`. A file registered under the same name is
+/// replaced.
+pub fn register(name: &str, data: &'static [u8]) -> Result<(), Error> {
+    if name.len() > MAX_NAME { return Err(Error::NameTooLong); }
+
+    let mut file = File { name: [0; MAX_NAME], name_len: name.len(), data };
+    file.name[..name.len()].copy_from_slice(name.as_bytes());
+
+    // Replace a file of the same name, or take a free slot
+    let mut files = FILES.lock();
+    let slot = files.files.iter().position(|f| {
+        f.is_some_and(|f| f.name() == name)
+    }).or_else(|| files.files.iter().position(|f| f.is_none()))
+      .ok_or(Error::TooManyFiles)?;
+
+    files.files[slot] = Some(file);
+    Ok(())
+}
+
+/// Returns the contents of the file registered under `name`
+pub fn get(name: &str) -> Option<&'static [u8]> {
+    FILES.lock().iter().find(|f| f.name() == name).map(|f| f.data())
+}
+
+/// Returns a copy of the registry
+pub fn files() -> Files {
+    *FILES.lock()
+}
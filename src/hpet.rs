@@ -0,0 +1,429 @@
+//! High Precision Event Timer driver
+//!
+//! The HPET is a free-running counter with a fixed, known period, which makes
+//! it a reference clock: it turns TSC cycles into wall time and tells whether
+//! the TSC ticks at a steady rate. Its comparators can also be used as
+//! one-shot timers, polled or routed to an IO APIC input.
+
+use core::sync::atomic::{ AtomicU64, Ordering };
+use crate::{ acpi, cpu };
+use crate::acpi::hpet::HpetTable;
+use crate::spinlock::SpinLock;
+
+/// Number of femtoseconds in a second
+pub const FEMTOS_PER_SEC: u64 = 1_000_000_000_000_000;
+
+/// Largest counter period the specification allows, 100 ns
+const MAX_PERIOD: u64 = 100_000_000;
+
+/// Highest TSC frequency in Hz we expect. Timeouts measured in TSC cycles
+/// before the TSC is calibrated assume it, so they last at least as long as
+/// they should.
+pub const MAX_TSC_FREQUENCY: u64 = 10_000_000_000;
+
+/// Number of nanoseconds the main counter gets to advance before we give up
+/// on it
+const STALL_TIMEOUT: u64 = 1_000_000;
+
+/// Maximum number of comparator timers of an HPET
+const MAX_TIMERS: usize = 32;
+
+/// Deadline of a timer that isn't armed
+const DISARMED: u64 = u64::MAX;
+
+/// The HPET found by [`init()`]
+static HPET: SpinLock<Option<Hpet>> = SpinLock::new(None);
+
+/// Last value read from a 32-bit main counter, extended to 64 bits
+static LAST_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// TSC frequency in Hz measured by [`Hpet::calibrate_tsc()`], 0 if unknown
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Deadline of every one-shot timer, [`DISARMED`] if it isn't armed
+static DEADLINES: [AtomicU64; MAX_TIMERS] =
+    [const { AtomicU64::new(DISARMED) }; MAX_TIMERS];
+
+// Registers of the HPET
+
+/// General capabilities and ID register
+const GCAP_ID: usize = 0x000;
+
+/// General configuration register
+const GEN_CONF: usize = 0x010;
+
+/// General interrupt status register
+const GINTR_STA: usize = 0x020;
+
+/// Main counter value register
+const MAIN_CNT: usize = 0x0F0;
+
+/// Configuration and capability register of timer `n`
+const fn timer_conf(n: usize) -> usize { 0x100 + 0x20 * n }
+
+/// Comparator value register of timer `n`
+const fn timer_comparator(n: usize) -> usize { 0x108 + 0x20 * n }
+
+// Bits of the general capabilities register
+
+/// The main counter is 64 bits wide
+const COUNT_SIZE_CAP: u64 = 1 << 13;
+
+// Bits of the general configuration register
+
+/// The main counter runs and the timers can fire
+const ENABLE_CNF: u64 = 1 << 0;
+
+/// Timers 0 and 1 replace the legacy PIT and RTC interrupts
+const LEG_RT_CNF: u64 = 1 << 1;
+
+// Bits of the timer configuration and capability registers
+
+/// Level-triggered interrupts; the status bit is only set in this mode
+const TN_INT_TYPE_CNF: u64 = 1 << 1;
+
+/// Interrupts of the timer are enabled
+const TN_INT_ENB_CNF: u64 = 1 << 2;
+
+/// The timer is periodic
+const TN_TYPE_CNF: u64 = 1 << 3;
+
+/// The timer runs in 32-bit mode
+const TN_32MODE_CNF: u64 = 1 << 8;
+
+/// Shift of the IO APIC input the timer is routed to
+const TN_INT_ROUTE_SHIFT: u64 = 9;
+
+/// Mask of the IO APIC input the timer is routed to
+const TN_INT_ROUTE_MASK: u64 = 0x1F << TN_INT_ROUTE_SHIFT;
+
+/// Interrupts are delivered as FSB messages
+const TN_FSB_EN_CNF: u64 = 1 << 14;
+
+/// Errors returned by the HPET routines
+#[derive(Debug)]
+pub enum Error {
+    /// Couldn't find the HPET table
+    Acpi(acpi::Error),
+
+    /// The registers aren't in system memory
+    NotMemoryMapped,
+
+    /// The counter period is 0 or larger than the specification allows
+    InvalidPeriod(u64),
+
+    /// The timer doesn't exist
+    NoSuchTimer,
+
+    /// The timer can't be routed to the IO APIC input
+    InvalidRoute(u32),
+
+    /// The main counter stopped advancing
+    Stalled,
+
+    /// The timer didn't fire in time
+    NeverFired,
+}
+
+/// An HPET with its main counter running
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    /// Address of the registers
+    base: usize,
+
+    /// Period of the main counter in femtoseconds
+    period: u64,
+
+    /// Number of timers
+    timers: usize,
+
+    /// Whether the main counter is 64 bits wide
+    counter_64: bool,
+}
+
+/// Returns the HPET found by [`init()`]
+pub fn hpet() -> Option<Hpet> {
+    *HPET.lock()
+}
+
+/// Find the HPET through ACPI and start its main counter.
+///
+/// Meant to be called after the boot services are exited; the firmware may
+/// use the HPET itself until then.
+pub fn init() -> Result<Hpet, Error> {
+    let table = acpi::Acpi::new()
+        .and_then(|acpi| acpi.find::<HpetTable>())
+        .map_err(Error::Acpi)?;
+
+    let gas = table.base_address;
+    if gas.address_space != acpi::ADDRESS_SPACE_MEMORY || !gas.is_present() {
+        return Err(Error::NotMemoryMapped);
+    }
+
+    let mut hpet = Hpet {
+        base:       gas.address as usize,
+        period:     0,
+        timers:     0,
+        counter_64: false,
+    };
+
+    // The period and the number of timers are in the capabilities
+    let caps = unsafe { hpet.read(GCAP_ID) };
+    hpet.period = caps >> 32;
+    hpet.timers = ((caps >> 8) & 0x1F) as usize + 1;
+    hpet.counter_64 = caps & COUNT_SIZE_CAP != 0;
+    if hpet.period == 0 || hpet.period > MAX_PERIOD {
+        return Err(Error::InvalidPeriod(hpet.period));
+    }
+
+    unsafe {
+        // Stop the counter, quiet every timer and start counting from 0
+        let conf = hpet.read(GEN_CONF) & !(ENABLE_CNF | LEG_RT_CNF);
+        hpet.write(GEN_CONF, conf);
+        for timer in 0..hpet.timers {
+            let tconf = hpet.read(timer_conf(timer));
+            hpet.write(timer_conf(timer),
+                       tconf & !(TN_INT_ENB_CNF | TN_TYPE_CNF));
+        }
+        hpet.write(MAIN_CNT, 0);
+        LAST_COUNT.store(0, Ordering::SeqCst);
+
+        hpet.write(GEN_CONF, conf | ENABLE_CNF);
+    }
+
+    // Everything waiting on the counter spins forever if it doesn't run
+    let timeout = tsc_deadline(STALL_TIMEOUT);
+    while hpet.counter() == 0 {
+        if tsc_passed(timeout) { return Err(Error::Stalled); }
+        core::hint::spin_loop();
+    }
+
+    *HPET.lock() = Some(hpet);
+    Ok(hpet)
+}
+
+/// Returns the TSC value at least `nanos` nanoseconds from now, assuming the
+/// TSC runs at [`MAX_TSC_FREQUENCY`] at most
+fn tsc_deadline(nanos: u64) -> u64 {
+    let cycles = nanos as u128 * MAX_TSC_FREQUENCY as u128 / 1_000_000_000;
+    (unsafe { cpu::rdtsc() } as u64).saturating_add(cycles as u64)
+}
+
+/// Returns whether the TSC is past `deadline`
+fn tsc_passed(deadline: u64) -> bool {
+    unsafe { cpu::rdtsc() as u64 > deadline }
+}
+
+impl Hpet {
+    /// Read the register at `offset`
+    unsafe fn read(&self, offset: usize) -> u64 {
+        unsafe { ((self.base + offset) as *const u64).read_volatile() }
+    }
+
+    /// Write `val` to the register at `offset`
+    unsafe fn write(&self, offset: usize, val: u64) {
+        unsafe { ((self.base + offset) as *mut u64).write_volatile(val) };
+    }
+
+    /// Returns the period of the main counter in femtoseconds
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// Returns the frequency of the main counter in Hz
+    pub fn frequency(&self) -> u64 {
+        FEMTOS_PER_SEC / self.period
+    }
+
+    /// Returns the number of comparator timers
+    pub fn timers(&self) -> usize {
+        self.timers
+    }
+
+    /// Returns the value of the main counter. It never goes backwards, even
+    /// if the counter is only 32 bits wide, as long as it's read at least once
+    /// per wraparound.
+    pub fn counter(&self) -> u64 {
+        let count = unsafe { self.read(MAIN_CNT) };
+        if self.counter_64 { return count; }
+
+        // Extend the 32-bit counter with the wraparounds we've seen
+        let extend = |last: u64| {
+            let val = (last & !0xFFFF_FFFF) | (count & 0xFFFF_FFFF);
+            if val < last { val + (1 << 32) } else { val }
+        };
+        let prev = LAST_COUNT.fetch_update(Ordering::SeqCst, Ordering::SeqCst,
+                                           |last| Some(extend(last)))
+            .unwrap();
+        extend(prev)
+    }
+
+    /// Returns the number of femtoseconds `ticks` of the main counter take
+    pub fn ticks_to_femtos(&self, ticks: u64) -> u128 {
+        ticks as u128 * self.period as u128
+    }
+
+    /// Returns the number of nanoseconds `ticks` of the main counter take
+    pub fn ticks_to_nanos(&self, ticks: u64) -> u64 {
+        (self.ticks_to_femtos(ticks) / 1_000_000) as u64
+    }
+
+    /// Returns the number of main counter ticks in `nanos` nanoseconds,
+    /// rounded up
+    pub fn nanos_to_ticks(&self, nanos: u64) -> u64 {
+        (nanos as u128 * 1_000_000).div_ceil(self.period as u128) as u64
+    }
+
+    /// Returns the number of nanoseconds since the counter was started
+    pub fn nanos(&self) -> u64 {
+        self.ticks_to_nanos(self.counter())
+    }
+
+    /// Spin for at least `nanos` nanoseconds
+    pub fn delay(&self, nanos: u64) {
+        let end = self.counter() + self.nanos_to_ticks(nanos);
+        while self.counter() < end { core::hint::spin_loop(); }
+    }
+
+    /// Arm `timer` to fire once the main counter reaches `deadline`.
+    ///
+    /// Whether it fired can be polled with [`Hpet::fired()`]. With `route`,
+    /// it additionally interrupts that IO APIC input, which the timer has to
+    /// support.
+    pub fn start_oneshot(&self, timer: usize, deadline: u64,
+                         route: Option<u32>) -> Result<(), Error> {
+        if timer >= self.timers { return Err(Error::NoSuchTimer); }
+
+        unsafe {
+            let caps = self.read(timer_conf(timer));
+
+            // The upper half of the register lists the inputs we may use
+            if let Some(route) = route &&
+                    (route >= 32 || (caps >> 32) & (1 << route) == 0) {
+                return Err(Error::InvalidRoute(route));
+            }
+
+            // Disarm it while it's reconfigured
+            let mut conf = caps & !(TN_INT_ENB_CNF | TN_TYPE_CNF |
+                                    TN_32MODE_CNF | TN_INT_ROUTE_MASK |
+                                    TN_FSB_EN_CNF);
+            self.write(timer_conf(timer), conf);
+
+            // Clear a stale status and set the deadline
+            self.write(GINTR_STA, 1 << timer);
+            self.write(timer_comparator(timer), deadline);
+            DEADLINES[timer].store(deadline, Ordering::SeqCst);
+
+            conf |= TN_INT_TYPE_CNF;
+            if let Some(route) = route {
+                conf |= (route as u64) << TN_INT_ROUTE_SHIFT | TN_INT_ENB_CNF;
+            }
+            self.write(timer_conf(timer), conf);
+        }
+        Ok(())
+    }
+
+    /// Arm `timer` to fire once, `nanos` nanoseconds from now. See
+    /// [`Hpet::start_oneshot()`].
+    pub fn start_oneshot_in(&self, timer: usize, nanos: u64,
+                            route: Option<u32>) -> Result<(), Error> {
+        let deadline = self.counter() + self.nanos_to_ticks(nanos);
+        self.start_oneshot(timer, deadline, route)
+    }
+
+    /// Returns whether `timer` fired since it was armed, acknowledging its
+    /// interrupt and disarming it if it did
+    pub fn fired(&self, timer: usize) -> Result<bool, Error> {
+        if timer >= self.timers { return Err(Error::NoSuchTimer); }
+
+        // The status bit isn't set while the interrupt is disabled, at least
+        // not by QEMU, so polled timers are checked against their deadline
+        let deadline = DEADLINES[timer].load(Ordering::SeqCst);
+        let status = unsafe { self.read(GINTR_STA) } & (1 << timer) != 0;
+        let fired = status ||
+            (deadline != DISARMED && self.counter() >= deadline);
+        if fired {
+            unsafe { self.write(GINTR_STA, 1 << timer) };
+            DEADLINES[timer].store(DISARMED, Ordering::SeqCst);
+        }
+        Ok(fired)
+    }
+
+    /// Arm `timer` to fire once, `nanos` nanoseconds from now, without an
+    /// interrupt and poll it until it fires. Returns the number of
+    /// nanoseconds that took.
+    pub fn check_oneshot(&self, timer: usize, nanos: u64)
+            -> Result<u64, Error> {
+        let start = self.counter();
+        self.start_oneshot(timer, start + self.nanos_to_ticks(nanos), None)?;
+
+        // Give it twice the time before calling it a failure
+        let timeout = tsc_deadline(nanos.saturating_mul(2).max(STALL_TIMEOUT));
+        while !self.fired(timer)? {
+            if tsc_passed(timeout) {
+                self.stop(timer)?;
+                return Err(Error::NeverFired);
+            }
+            core::hint::spin_loop();
+        }
+        self.stop(timer)?;
+        Ok(self.ticks_to_nanos(self.counter() - start))
+    }
+
+    /// Stop `timer` from firing
+    pub fn stop(&self, timer: usize) -> Result<(), Error> {
+        if timer >= self.timers { return Err(Error::NoSuchTimer); }
+
+        unsafe {
+            let conf = self.read(timer_conf(timer));
+            self.write(timer_conf(timer), conf & !TN_INT_ENB_CNF);
+            self.write(GINTR_STA, 1 << timer);
+        }
+        DEADLINES[timer].store(DISARMED, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Measure the frequency of the TSC against the HPET over `nanos`
+    /// nanoseconds and remember it for [`tsc_frequency()`]. Returns the
+    /// frequency in Hz.
+    pub fn calibrate_tsc(&self, nanos: u64) -> Result<u64, Error> {
+        // Give up if the counter stops, allowing twice the time we measure
+        let timeout = tsc_deadline(nanos.saturating_mul(2).max(STALL_TIMEOUT));
+        let counter = || {
+            if tsc_passed(timeout) { return Err(Error::Stalled); }
+            Ok(self.counter())
+        };
+
+        // Start right after a tick so we don't measure a partial one
+        let edge = counter()?;
+        let mut start = edge;
+        while start == edge { start = counter()?; }
+        let tsc_start = unsafe { cpu::rdtsc() } as u64;
+
+        let end = start + self.nanos_to_ticks(nanos).max(1);
+        let mut now = start;
+        while now < end { now = counter()?; }
+        let tsc_end = unsafe { cpu::rdtsc() } as u64;
+
+        let femtos = self.ticks_to_femtos(now - start);
+        let freq = ((tsc_end - tsc_start) as u128 * FEMTOS_PER_SEC as u128 /
+                    femtos) as u64;
+        TSC_FREQUENCY.store(freq, Ordering::SeqCst);
+        Ok(freq)
+    }
+}
+
+/// Returns the TSC frequency in Hz measured by [`Hpet::calibrate_tsc()`]
+pub fn tsc_frequency() -> Option<u64> {
+    match TSC_FREQUENCY.load(Ordering::SeqCst) {
+        0    => None,
+        freq => Some(freq),
+    }
+}
+
+/// Returns the number of nanoseconds `cycles` TSC cycles take, if the TSC has
+/// been calibrated
+pub fn cycles_to_nanos(cycles: u64) -> Option<u64> {
+    let freq = tsc_frequency()?;
+    Some((cycles as u128 * 1_000_000_000 / freq as u128) as u64)
+}
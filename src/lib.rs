@@ -1,10 +1,26 @@
 #![no_std]
 #![feature(alloc_error_handler)]
 
-#[macro_use] pub mod serial;
+extern crate alloc;
+
+#[macro_use] pub mod console;
+pub mod serial;
 pub mod cpu;
 pub mod rangeset;
 pub mod spinlock;
 pub mod efi;
 pub mod panic;
 pub mod mm;
+pub mod bench;
+pub mod boot;
+pub mod cmdline;
+pub mod files;
+pub mod font;
+pub mod fbcon;
+pub mod rng;
+pub mod smp;
+pub mod results;
+pub mod acpi;
+pub mod power;
+pub mod qemu;
+pub mod hpet;
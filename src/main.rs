@@ -1,23 +1,29 @@
 #![no_std]
 #![no_main]
 
-use kernel::{ efi, serial, mm };
+use kernel::{ bench, benchmark, boot::BootInfo, rng::Rng };
 
-#[unsafe(no_mangle)]
-fn efi_main(img_handle: efi::Handle,
-            sys_table: *mut efi::SystemTable) -> efi::Status {
-    // Initialize the serial driver
-    serial::Serial::init();
+// Benchmarks can be registered from any module; this one is just an example.
+benchmark!(sum_4k, bench::Bench::new().iters(100), |bench| {
+    let data = [1u64; 4096];
+    bench.run(|| data.iter().sum::<u64>())
+});
 
-    // Get the free memory map and exit the boot services.
-    let memory = unsafe { efi::memory_map_exit(img_handle, sys_table) };
+// Random inputs come from the seeded PRNG, so a run can be replayed with the
+// seed it reports
+benchmark!(sort_1k, bench::Bench::new().iters(100), |bench| {
+    let mut rng = bench.rng();
+    let data: [u64; 1024] = core::array::from_fn(|_| rng.next_u64());
+    bench.run(|| {
+        let mut data = data;
+        data.sort_unstable();
+        data[0]
+    })
+});
 
-    // Initialize the memory manager
-    // UEFI automatically sets up 1:1 paging, so each access is direct to
-    // physical memory.
-    mm::init(memory.expect("Couldn't acquire the free memory map."));
+kernel::entry!(main);
 
-    // Your code here :)
-
-    panic!("Reached end of execution.");
+fn main(boot: &BootInfo) {
+    // Run the registered benchmarks, or only the ones selected by `bench=`
+    bench::run_all(boot.cmdline.get("bench"));
 }
@@ -1,8 +1,12 @@
 use core::panic::PanicInfo;
-use crate::cpu;
+use crate::{ cpu, efi, qemu, serial };
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // Without a registered system table, e.g. because it failed validation,
+    // there's no console yet. Fall back to the serial ports as they are
+    if efi::system_table().is_none() { serial::Serial::init_early(); }
+
     // Print the location info
     if let Some(loc) = info.location() {
        print_shatter!("!!! PANIC !!! {} {}:{} ----",
@@ -12,6 +16,7 @@ fn panic(info: &PanicInfo) -> ! {
     // Print the message
     print_shatter!(" {} ----\n", info.message());
 
-    // And halt
+    // Tell the host we failed, if it's listening, and halt
+    qemu::exit(qemu::ExitCode::Failure);
     unsafe { cpu::halt() };
 }
@@ -0,0 +1,180 @@
+//! Shutting down and rebooting
+//!
+//! ACPI comes first: the FADT reset register for reboots and the `\_S5` sleep
+//! state through the PM1 control registers for powering off. If that doesn't
+//! work, the runtime services get their turn, and if even they fail, the CPU
+//! is halted.
+
+use crate::{ acpi, cpu, efi };
+use crate::acpi::Acpi;
+use crate::acpi::aml::{ self, Dsdt };
+use crate::acpi::fadt::{ self, Fadt };
+
+/// Sleep state of soft-off
+const S5: u8 = 5;
+
+/// Number of spins to wait for the ACPI mode to be entered or for the system
+/// to go away
+const TIMEOUT_SPINS: usize = 1 << 24;
+
+/// I/O port of the command register of the 8042 keyboard controller
+const KBC_COMMAND: u16 = 0x64;
+
+/// 8042 command pulsing the CPU reset line
+const KBC_RESET: u8 = 0xFE;
+
+/// Errors returned by the power routines
+#[derive(Debug)]
+pub enum Error {
+    /// Couldn't get a table or access a register
+    Acpi(acpi::Error),
+
+    /// The FADT has no reset register
+    NoResetRegister,
+
+    /// The FADT has no PM1a control register
+    NoPm1Control,
+
+    /// Neither the DSDT nor the SSDTs define the sleep state
+    NoSleepType(u8),
+
+    /// The system is still running after asking it to go away
+    StillRunning,
+
+    /// The runtime services aren't available
+    NoRuntimeServices,
+
+    /// The runtime services couldn't do the reset
+    ResetFailed(efi::status::Error),
+}
+
+impl From<acpi::Error> for Error {
+    fn from(err: acpi::Error) -> Self {
+        Error::Acpi(err)
+    }
+}
+
+/// Returns the FADT of the system
+fn fadt() -> Result<&'static Fadt, Error> {
+    Ok(Acpi::new()?.find::<Fadt>()?)
+}
+
+/// Spin for a while, giving a request to go away time to take effect
+fn wait() -> Error {
+    for _ in 0..TIMEOUT_SPINS { core::hint::spin_loop(); }
+    Error::StillRunning
+}
+
+/// Reset the system through the FADT reset register
+pub fn acpi_reset() -> Result<(), Error> {
+    let (reg, val) = fadt()?.reset_register().ok_or(Error::NoResetRegister)?;
+    unsafe { reg.write(val as u64)? };
+    Err(wait())
+}
+
+/// Returns the SLP_TYPa and SLP_TYPb values of the sleep state `state` from
+/// the DSDT, or the SSDTs if the DSDT doesn't define it
+pub fn sleep_type(fadt: &Fadt, state: u8) -> Result<(u8, u8), Error> {
+    if let Some(slp_typ) = aml::sleep_type(fadt.dsdt()?.aml(), state) {
+        return Ok(slp_typ);
+    }
+
+    // SSDTs are definition blocks just like the DSDT, with the same layout
+    Acpi::new()?.tables()
+        .flatten()
+        .filter(|table| table.signature == aml::SSDT)
+        .find_map(|table| {
+            let ssdt = unsafe { &*(table as *const _ as *const Dsdt) };
+            aml::sleep_type(ssdt.aml(), state)
+        })
+        .ok_or(Error::NoSleepType(state))
+}
+
+/// Switch the platform from legacy to ACPI mode, if the firmware hasn't
+/// already. Writes to the PM1 control registers may be ignored otherwise.
+fn enable_acpi_mode(fadt: &Fadt, pm1a: &acpi::GenericAddress)
+        -> Result<(), Error> {
+    let enabled = || -> Result<bool, Error> {
+        Ok(unsafe { pm1a.read()? } as u16 & fadt::SCI_EN != 0)
+    };
+
+    // Nothing to do if we're already in ACPI mode or the platform has no
+    // legacy mode to begin with
+    let (smi_cmd, acpi_enable) = (fadt.smi_cmd, fadt.acpi_enable);
+    if enabled()? || smi_cmd == 0 || acpi_enable == 0 { return Ok(()); }
+
+    unsafe { cpu::out8(smi_cmd as u16 as *const u16, acpi_enable) };
+    for _ in 0..TIMEOUT_SPINS {
+        if enabled()? { break; }
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
+
+/// Power the system off by entering the `\_S5` sleep state
+pub fn acpi_shutdown() -> Result<(), Error> {
+    let fadt = fadt()?;
+    let pm1a = fadt.pm1a_control().ok_or(Error::NoPm1Control)?;
+    let (slp_typa, slp_typb) = sleep_type(fadt, S5)?;
+
+    enable_acpi_mode(fadt, &pm1a)?;
+
+    // Set the sleep type and enable sleeping, PM1b first as PM1a may take
+    // effect immediately
+    let enter = |reg: &acpi::GenericAddress, slp_typ: u8| unsafe {
+        let val = reg.read()? as u16 & !fadt::SLP_TYP_MASK;
+        let slp_typ = (slp_typ as u16) << fadt::SLP_TYP_SHIFT &
+            fadt::SLP_TYP_MASK;
+        reg.write((val | slp_typ | fadt::SLP_EN) as u64)
+    };
+    if let Some(pm1b) = fadt.pm1b_control() { enter(&pm1b, slp_typb)?; }
+    enter(&pm1a, slp_typa)?;
+
+    Err(wait())
+}
+
+/// Ask the runtime services for a reset of `reset_type`. Only returns if they
+/// aren't around or couldn't do it.
+pub fn efi_reset(reset_type: efi::ResetType) -> Result<(), Error> {
+    let runtime_svc = efi::runtime_services().ok_or(Error::NoRuntimeServices)?;
+    Err(Error::ResetFailed(
+        runtime_svc.reset_system(reset_type, efi::RawStatus::SUCCESS)))
+}
+
+/// Power the system off, or halt if it can't be.
+///
+/// Meant to be called after the boot services are exited; until then, the
+/// firmware owns the ACPI hardware.
+pub fn shutdown() -> ! {
+    if let Err(err) = acpi_shutdown() {
+        print!("ACPI shutdown failed: {:?}\n", err);
+    }
+
+    if let Err(err) = efi_reset(efi::ResetType::Shutdown) {
+        print!("EFI shutdown failed: {:?}\n", err);
+    }
+
+    print!("Couldn't power off, halting\n");
+    unsafe { cpu::halt() };
+}
+
+/// Reboot the system, or halt if it can't be.
+///
+/// Meant to be called after the boot services are exited; until then, the
+/// firmware owns the ACPI hardware.
+pub fn reboot() -> ! {
+    if let Err(err) = acpi_reset() {
+        print!("ACPI reset failed: {:?}\n", err);
+    }
+
+    if let Err(err) = efi_reset(efi::ResetType::Cold) {
+        print!("EFI reset failed: {:?}\n", err);
+    }
+
+    // Last resort: have the keyboard controller pulse the reset line
+    unsafe { cpu::out8(KBC_COMMAND as *const u16, KBC_RESET) };
+    let _ = wait();
+
+    print!("Couldn't reboot, halting\n");
+    unsafe { cpu::halt() };
+}
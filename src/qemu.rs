@@ -0,0 +1,148 @@
+//! QEMU's isa-debug-exit device
+//!
+//! Writing a value `v` to the I/O port of the device makes QEMU exit with the
+//! status `(v << 1) | 1`, which lets automated runs tell the host whether they
+//! passed. The device can sit at any port, so it's only used if we're told
+//! where: through the fw_cfg file `opt/isa-debug-exit/iobase`, which is read
+//! first thing at boot so early panics are reported too, or with
+//! `debug_exit=PORT` on the command line, which overrides it once parsed. A
+//! bare `debug_exit` uses the usual [`DEFAULT_PORT`] and `debug_exit=off`
+//! disables it.
+
+use core::sync::atomic::{ AtomicU32, Ordering };
+use crate::cpu::{ self, in8, out16, out32 };
+use crate::cmdline::{ self, Cmdline };
+
+/// The port isa-debug-exit is usually configured to
+pub const DEFAULT_PORT: u16 = 0xF4;
+
+/// Name of the fw_cfg file holding the port of the device
+pub const FW_CFG_FILE: &str = "opt/isa-debug-exit/iobase";
+
+/// The port of the device, or 0 if there's none
+static PORT: AtomicU32 = AtomicU32::new(0);
+
+/// I/O port selecting the fw_cfg item
+const FW_CFG_SELECTOR: u16 = 0x510;
+
+/// I/O port the selected fw_cfg item is read from
+const FW_CFG_DATA: u16 = 0x511;
+
+/// fw_cfg item holding the "QEMU" signature
+const FW_CFG_SIGNATURE: u16 = 0x0000;
+
+/// fw_cfg item holding the directory of fw_cfg files
+const FW_CFG_FILE_DIR: u16 = 0x0019;
+
+/// Maximum length of the name of a fw_cfg file, including the terminator
+const FW_CFG_MAX_NAME: usize = 56;
+
+/// Status reported to the host through [`exit()`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u32)]
+pub enum ExitCode {
+    /// Everything went fine; QEMU exits with 1
+    Success = 0,
+
+    /// Something went wrong; QEMU exits with 3
+    Failure = 1,
+}
+
+/// Read the next `buf.len()` bytes of the selected fw_cfg item
+unsafe fn fw_cfg_read_next(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        *byte = unsafe { in8(FW_CFG_DATA as *const u16) };
+    }
+}
+
+/// Select the fw_cfg item `key` and read the first `buf.len()` bytes of it
+unsafe fn fw_cfg_read(key: u16, buf: &mut [u8]) {
+    unsafe {
+        out16(FW_CFG_SELECTOR as *const u16, key);
+        fw_cfg_read_next(buf);
+    }
+}
+
+/// Returns the fw_cfg item of the file `name`, and its size in bytes
+fn fw_cfg_find(name: &str) -> Option<(u16, usize)> {
+    // Don't poke at ports of real hardware
+    if cpu::cpuid(1, 0).2 & (1 << 31) == 0 { return None; }
+
+    let mut signature = [0u8; 4];
+    unsafe { fw_cfg_read(FW_CFG_SIGNATURE, &mut signature) };
+    if &signature != b"QEMU" { return None; }
+
+    // The directory is a big-endian count followed by the files, each a
+    // big-endian size, item and reserved word, and the name
+    let mut count = [0u8; 4];
+    unsafe { fw_cfg_read(FW_CFG_FILE_DIR, &mut count) };
+
+    for _ in 0..u32::from_be_bytes(count) {
+        let mut size = [0u8; 4];
+        let mut select = [0u8; 4];
+        let mut file = [0u8; FW_CFG_MAX_NAME];
+        unsafe {
+            fw_cfg_read_next(&mut size);
+            fw_cfg_read_next(&mut select);
+            fw_cfg_read_next(&mut file);
+        }
+
+        let len = file.iter().position(|&byte| byte == 0).unwrap_or(file.len());
+        if &file[..len] == name.as_bytes() {
+            return Some((u16::from_be_bytes([select[0], select[1]]),
+                         u32::from_be_bytes(size) as usize));
+        }
+    }
+    None
+}
+
+/// Returns the port of the device from the fw_cfg file [`FW_CFG_FILE`]
+fn fw_cfg_port() -> Option<u16> {
+    let (key, size) = fw_cfg_find(FW_CFG_FILE)?;
+
+    let mut buf = [0u8; 16];
+    let buf = buf.get_mut(..size)?;
+    unsafe { fw_cfg_read(key, buf) };
+
+    let value = core::str::from_utf8(buf).ok()?.trim_end_matches('\0').trim();
+    cmdline::parse_usize(value)?.try_into().ok()
+}
+
+/// Find the device through fw_cfg. Only touches I/O ports, so it can be
+/// called before anything else at boot. Returns its port, if there is one.
+pub fn probe() -> Option<u16> {
+    let port = fw_cfg_port();
+    PORT.store(port.unwrap_or(0) as u32, Ordering::SeqCst);
+    port
+}
+
+/// Apply `debug_exit` on `cmdline`, which overrides what [`probe()`] found.
+/// Returns the port of the device, if there is one.
+pub fn init(cmdline: &Cmdline) -> Option<u16> {
+    let port = match cmdline.get("debug_exit") {
+        Some("off") => None,
+        Some("")    => Some(DEFAULT_PORT),
+        Some(port)  => cmdline::parse_usize(port)
+            .and_then(|port| port.try_into().ok()),
+        None        => return self::port(),
+    };
+
+    PORT.store(port.unwrap_or(0) as u32, Ordering::SeqCst);
+    port
+}
+
+/// Returns the port of the device, if it was found by [`probe()`] or
+/// [`init()`]
+pub fn port() -> Option<u16> {
+    match PORT.load(Ordering::SeqCst) {
+        0    => None,
+        port => Some(port as u16),
+    }
+}
+
+/// Make QEMU exit, reporting `code` to the host. Returns if there's no
+/// device.
+pub fn exit(code: ExitCode) {
+    let Some(port) = port() else { return; };
+    unsafe { out32(port as *const u16, code as u32) };
+}
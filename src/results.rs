@@ -0,0 +1,260 @@
+//! Benchmark results persisted across boots
+//!
+//! The results of every benchmark are kept in a non-volatile UEFI variable
+//! named after the benchmark, under our own vendor GUID. Every run appends a
+//! compact record to it, so the same benchmark can be compared across cold
+//! boots without a network or disk driver. The firmware needs somewhere to
+//! store the variables for this to work, e.g. a writable OVMF VARS file.
+
+use core::fmt;
+use crate::efi::{ self, Guid };
+use crate::bench::Stats;
+
+/// Vendor GUID all of our variables are stored under
+pub const VENDOR_GUID: Guid = Guid::new(0x75d39ddd, 0xdf15, 0x4c72,
+    [0xa0, 0xb0, 0xe1, 0xa5, 0xf1, 0x01, 0xc5, 0x99]);
+
+/// Maximum number of records kept per benchmark. Once full, the oldest record
+/// is dropped.
+pub const MAX_RECORDS: usize = 32;
+
+/// Maximum length of a benchmark name that can be persisted, in characters
+const MAX_NAME: usize = 64;
+
+/// Magic at the start of every variable, "BNCH"
+const MAGIC: u32 = 0x48434E42;
+
+/// Version of the variable layout
+const VERSION: u32 = 1;
+
+/// Size of the header of a variable in bytes
+const HEADER_SIZE: usize = 8;
+
+/// Size of a single record in bytes
+const RECORD_SIZE: usize = 7 * 8;
+
+/// Attributes of our variables
+const ATTRIBUTES: u32 = efi::VARIABLE_NON_VOLATILE |
+                        efi::VARIABLE_BOOTSERVICE_ACCESS |
+                        efi::VARIABLE_RUNTIME_ACCESS;
+
+/// Errors returned by the result routines
+#[derive(Debug)]
+pub enum Error {
+    /// The runtime services aren't available
+    NoRuntimeServices,
+
+    /// The name is longer than [`MAX_NAME`] characters or isn't UCS-2
+    InvalidName,
+
+    /// Couldn't read the variable
+    GetVariableFailed(efi::status::Error),
+
+    /// Couldn't write the variable
+    SetVariableFailed(efi::status::Error),
+
+    /// The variable doesn't contain records we understand
+    Corrupted,
+}
+
+/// Results of a single run of a benchmark. All values are in TSC cycles.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Record {
+    /// Number of timed runs
+    pub iters: u64,
+
+    /// Fastest run
+    pub min: u64,
+
+    /// Median run
+    pub median: u64,
+
+    /// Arithmetic mean of all runs
+    pub mean: u64,
+
+    /// Population standard deviation of all runs
+    pub stddev: u64,
+
+    /// 99th percentile
+    pub p99: u64,
+
+    /// Seed the inputs were generated from
+    pub seed: u64,
+}
+
+impl From<&Stats> for Record {
+    fn from(stats: &Stats) -> Self {
+        Self {
+            iters:  stats.iters as u64,
+            min:    stats.min,
+            median: stats.median,
+            mean:   stats.mean,
+            stddev: stats.stddev,
+            p99:    stats.p99,
+            seed:   stats.seed,
+        }
+    }
+}
+
+impl Record {
+    /// Returns the fields of the record in the order they're stored in
+    fn fields(&self) -> [u64; RECORD_SIZE / 8] {
+        [self.iters, self.min, self.median, self.mean, self.stddev, self.p99,
+         self.seed]
+    }
+
+    /// Parse a record from the little-endian `bytes`
+    fn parse(bytes: &[u8]) -> Self {
+        let field = |idx: usize| {
+            u64::from_le_bytes(bytes[idx * 8..][..8].try_into().unwrap())
+        };
+        Self {
+            iters:  field(0),
+            min:    field(1),
+            median: field(2),
+            mean:   field(3),
+            stddev: field(4),
+            p99:    field(5),
+            seed:   field(6),
+        }
+    }
+}
+
+/// The persisted records of a benchmark, oldest first
+#[derive(Debug, Clone, Copy)]
+pub struct History {
+    /// The records
+    records: [Record; MAX_RECORDS],
+
+    /// Number of valid records
+    len: usize,
+}
+
+impl History {
+    /// Returns an empty history
+    fn new() -> Self {
+        Self { records: [Record::default(); MAX_RECORDS], len: 0 }
+    }
+
+    /// Returns the records, oldest first
+    pub fn records(&self) -> &[Record] {
+        &self.records[..self.len]
+    }
+
+    /// Append `record`, dropping the oldest record if the history is full
+    fn push(&mut self, record: Record) {
+        if self.len == MAX_RECORDS {
+            self.records.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.records[self.len] = record;
+        self.len += 1;
+    }
+
+    /// Returns the median of the medians of all records, which is robust
+    /// against the odd boot with a noisy neighbor
+    pub fn median_of_medians(&self) -> Option<u64> {
+        if self.len == 0 { return None; }
+
+        let mut medians = [0u64; MAX_RECORDS];
+        let medians = &mut medians[..self.len];
+        for (median, record) in medians.iter_mut().zip(self.records()) {
+            *median = record.median;
+        }
+        Some(Stats::new(medians).median)
+    }
+}
+
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let best = self.records().iter().map(|r| r.median).min();
+        let worst = self.records().iter().map(|r| r.median).max();
+        match (self.median_of_medians(), best, worst) {
+            (Some(median), Some(best), Some(worst)) => {
+                write!(f, "median {:>10} | best {:>10} | worst {:>10} cycles \
+                           over {} boots", median, best, worst, self.len)
+            },
+            _ => write!(f, "no results"),
+        }
+    }
+}
+
+/// Encode `name` into the null-terminated UCS-2 variable name in `buf`
+fn variable_name<'a>(name: &str, buf: &'a mut [u16; MAX_NAME + 1])
+        -> Result<&'a [u16], Error> {
+    efi::ucs2::encode(name, buf).ok_or(Error::InvalidName)
+}
+
+/// Returns the persisted records of the benchmark `name`
+pub fn load(name: &str) -> Result<History, Error> {
+    let runtime_svc = efi::runtime_services().ok_or(Error::NoRuntimeServices)?;
+    let mut buf = [0u16; MAX_NAME + 1];
+    let var = variable_name(name, &mut buf)?;
+
+    // Read the variable; there's no history if it doesn't exist
+    let mut data = [0u8; HEADER_SIZE + MAX_RECORDS * RECORD_SIZE];
+    let size = match runtime_svc.get_variable(var, &VENDOR_GUID, &mut data) {
+        Ok((size, _)) => size,
+        Err(efi::status::Error::NotFound) => return Ok(History::new()),
+        Err(err) => return Err(Error::GetVariableFailed(err)),
+    };
+
+    // Make sure it's ours and complete
+    let data = &data[..size];
+    if size < HEADER_SIZE ||
+            !(size - HEADER_SIZE).is_multiple_of(RECORD_SIZE) ||
+            data[..4] != MAGIC.to_le_bytes() ||
+            data[4..8] != VERSION.to_le_bytes() {
+        return Err(Error::Corrupted);
+    }
+
+    let mut history = History::new();
+    for record in data[HEADER_SIZE..].chunks_exact(RECORD_SIZE) {
+        history.push(Record::parse(record));
+    }
+    Ok(history)
+}
+
+/// Write `history` as the persisted records of the benchmark `name`
+fn store(name: &str, history: &History) -> Result<(), Error> {
+    let runtime_svc = efi::runtime_services().ok_or(Error::NoRuntimeServices)?;
+    let mut buf = [0u16; MAX_NAME + 1];
+    let var = variable_name(name, &mut buf)?;
+
+    // Serialize the header and the records
+    let mut data = [0u8; HEADER_SIZE + MAX_RECORDS * RECORD_SIZE];
+    data[..4].copy_from_slice(&MAGIC.to_le_bytes());
+    data[4..8].copy_from_slice(&VERSION.to_le_bytes());
+    let records = data[HEADER_SIZE..].chunks_exact_mut(RECORD_SIZE);
+    for (bytes, record) in records.zip(history.records()) {
+        for (bytes, field) in bytes.chunks_exact_mut(8).zip(record.fields()) {
+            bytes.copy_from_slice(&field.to_le_bytes());
+        }
+    }
+
+    let size = HEADER_SIZE + history.len * RECORD_SIZE;
+    runtime_svc.set_variable(var, &VENDOR_GUID, ATTRIBUTES, &data[..size])
+        .map_err(Error::SetVariableFailed)
+}
+
+/// Append the results of a run of the benchmark `name` to its persisted
+/// records and return all of them, including the new one
+pub fn record(name: &str, stats: &Stats) -> Result<History, Error> {
+    let mut history = load(name)?;
+    history.push(Record::from(stats));
+    store(name, &history)?;
+    Ok(history)
+}
+
+/// Delete the persisted records of the benchmark `name`
+pub fn clear(name: &str) -> Result<(), Error> {
+    let runtime_svc = efi::runtime_services().ok_or(Error::NoRuntimeServices)?;
+    let mut buf = [0u16; MAX_NAME + 1];
+    let var = variable_name(name, &mut buf)?;
+
+    // Deleting a variable that doesn't exist is fine with us
+    match runtime_svc.set_variable(var, &VENDOR_GUID, ATTRIBUTES, &[]) {
+        Ok(()) | Err(efi::status::Error::NotFound) => Ok(()),
+        Err(err) => Err(Error::SetVariableFailed(err)),
+    }
+}
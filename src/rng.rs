@@ -0,0 +1,204 @@
+//! Random numbers
+//!
+//! Entropy comes from the firmware's EFI_RNG_PROTOCOL while the boot services
+//! are around and from RDSEED or RDRAND afterwards. It's only used for seeding
+//! though; the numbers themselves come from small and fast PRNGs, which produce
+//! the same sequence for the same seed so benchmark inputs can be replayed.
+
+use crate::{ cpu, efi };
+use crate::spinlock::SpinLock;
+
+/// Generator seeded with entropy from the firmware before the boot services
+/// were exited. Used if the CPU has neither RDSEED nor RDRAND.
+static FIRMWARE_ENTROPY: SpinLock<Option<SplitMix64>> = SpinLock::new(None);
+
+/// Returns 64 bits of entropy from the firmware's EFI_RNG_PROTOCOL, if the
+/// boot services are still around and the firmware has it
+fn firmware_entropy() -> Option<u64> {
+    if efi::boot_services_exited() { return None; }
+
+    let boot_svc = unsafe { efi::system_table()?.boot_svc.as_ref()? };
+    let rng = boot_svc.locate_protocol::<efi::rng::Rng>().ok()?;
+
+    let mut buf = [0u8; 8];
+    unsafe { (*rng).get_rng(&mut buf) }.ok()?;
+    Some(u64::from_le_bytes(buf))
+}
+
+/// Save entropy from the firmware for use after the boot services are exited.
+///
+/// Returns whether the firmware provided any.
+pub fn collect_firmware_entropy() -> bool {
+    let Some(seed) = firmware_entropy() else { return false; };
+    *FIRMWARE_ENTROPY.lock() = Some(SplitMix64::new(seed));
+    true
+}
+
+/// Returns 64 bits of entropy, or `None` if there's no source of it.
+///
+/// The firmware is asked while the boot services are around, then the CPU's
+/// RDSEED and RDRAND, and finally the generator seeded by
+/// [`collect_firmware_entropy()`].
+pub fn entropy() -> Option<u64> {
+    // While the boot services are around, the firmware knows best
+    if let Some(val) = firmware_entropy() { return Some(val); }
+
+    // Prefer the CPU's entropy source over its DRBG
+    if cpu::has_rdseed() && let Some(val) = unsafe { cpu::rdseed() } {
+        return Some(val);
+    }
+    if cpu::has_rdrand() && let Some(val) = unsafe { cpu::rdrand() } {
+        return Some(val);
+    }
+
+    FIRMWARE_ENTROPY.lock().as_mut().map(|rng| rng.next_u64())
+}
+
+/// Returns a fresh seed for a PRNG. Falls back to the TSC if there's no
+/// [`entropy()`], which is good enough for benchmark inputs.
+pub fn seed() -> u64 {
+    entropy().unwrap_or_else(|| {
+        SplitMix64::new(unsafe { cpu::rdtsc() } as u64).next_u64()
+    })
+}
+
+/// A pseudo-random number generator
+pub trait Rng {
+    /// Returns the next 64 random bits
+    fn next_u64(&mut self) -> u64;
+
+    /// Returns the next 32 random bits
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Returns a uniformly distributed number in `0..bound`.
+    ///
+    /// Panics if `bound` is 0.
+    fn below(&mut self, bound: u64) -> u64 {
+        assert!(bound != 0, "Can't pick a number below 0");
+
+        // Lemire's multiply-shift, rejecting the few products which would
+        // skew the distribution
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let prod = self.next_u64() as u128 * bound as u128;
+            if prod as u64 >= threshold { return (prod >> 64) as u64; }
+        }
+    }
+
+    /// Fill `buf` with random bytes
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// SplitMix64. Tiny and fast, mostly used to expand a single seed into the
+/// state of the other generators.
+#[derive(Debug, Clone, Copy)]
+pub struct SplitMix64 {
+    /// Current state
+    state: u64,
+}
+
+impl SplitMix64 {
+    /// Returns a new generator seeded with `seed`
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+}
+
+impl Rng for SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// xoshiro256**, a fast all-purpose generator with 256 bits of state
+#[derive(Debug, Clone, Copy)]
+pub struct Xoshiro256StarStar {
+    /// Current state
+    state: [u64; 4],
+}
+
+impl Xoshiro256StarStar {
+    /// Returns a new generator seeded with `seed`
+    pub fn new(seed: u64) -> Self {
+        // Expand the seed; SplitMix64 never produces an all-zero state
+        let mut seeder = SplitMix64::new(seed);
+        Self {
+            state: core::array::from_fn(|_| seeder.next_u64()),
+        }
+    }
+}
+
+impl Rng for Xoshiro256StarStar {
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let ret = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+
+        ret
+    }
+}
+
+/// PCG32 (XSH RR), a small generator producing 32 bits at a time
+#[derive(Debug, Clone, Copy)]
+pub struct Pcg32 {
+    /// Current state
+    state: u64,
+
+    /// Increment selecting the stream; always odd
+    inc: u64,
+}
+
+impl Pcg32 {
+    /// Multiplier of the underlying LCG
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    /// Returns a new generator seeded with `seed`, producing the sequence
+    /// selected by `stream`
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut pcg = Self { state: 0, inc: (stream << 1) | 1 };
+        pcg.step();
+        pcg.state = pcg.state.wrapping_add(seed);
+        pcg.step();
+        pcg
+    }
+
+    /// Advance the underlying LCG
+    fn step(&mut self) {
+        self.state = self.state.wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(self.inc);
+    }
+}
+
+impl Rng for Pcg32 {
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.step();
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let high = self.next_u32() as u64;
+        let low = self.next_u32() as u64;
+        (high << 32) | low
+    }
+}
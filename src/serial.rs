@@ -1,10 +1,13 @@
 //! A serial port driver
 //!
-//! This driver doesn't enumerate any tables but rather uses the good old
+//! This driver drives 16550-compatible UARTs. The firmware's console UART is
+//! taken from the ACPI SPCR table, which describes where its registers are and
+//! how it's configured. Without a usable SPCR, it falls back to the good old
 //! hardcoded ports. Chances of this not working on your hardware are small but
 //! if you don't see any output, you might wanna fiddle with this code.
 
 use core::fmt::Write;
+use crate::acpi::{ self, spcr::Spcr };
 use crate::spinlock::SpinLock;
 use crate::cpu::{ in8, out8 };
 
@@ -14,21 +17,190 @@ use crate::cpu::{ in8, out8 };
 /// because the print macro doesn't have access to any arguments.
 pub static SERIAL_DRIVER: SpinLock<Option<Serial>> = SpinLock::new(None);
 
-/// Addreses of the serial ports that are to be used by this serial driver
-pub const PORT_ADDRESSES: [*const u16; 2] = [
-    0x2F8 as *const u16,
-    0x3F8 as *const u16,
+/// I/O ports of the serial ports used if there's no usable SPCR
+pub const PORT_ADDRESSES: [u16; 2] = [
+    0x2F8,
+    0x3F8,
 ];
 
-/// A more-or-less dummy struct that implements `Write` such that `print!()` can
-/// be used on it
-pub struct Serial;
+/// Baud rate the fallback ports are configured to
+pub const FALLBACK_BAUD: u32 = 28800;
+
+/// Frequency of the input clock of a PC UART in Hz
+pub const DEFAULT_CLOCK: u32 = 1_843_200;
+
+/// Maximum number of serial ports the driver writes to
+const MAX_PORTS: usize = PORT_ADDRESSES.len();
+
+// Registers of a 16550, in units of the register stride
+
+/// Receive buffer (read) and transmit holding register (write)
+const DATA: usize = 0;
+
+/// Interrupt enable register, or the divisor high byte with DLAB set
+const INTERRUPT_ENABLE: usize = 1;
+
+/// Line control register
+const LINE_CONTROL: usize = 3;
+
+/// Modem control register
+const MODEM_CONTROL: usize = 4;
+
+/// Line status register
+const LINE_STATUS: usize = 5;
+
+/// How the registers of a UART are accessed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Registers {
+    /// Consecutive I/O ports starting at the base port
+    Io(u16),
+
+    /// Memory-mapped registers, each `width` bytes wide and accessed as such
+    Mmio { base: usize, width: usize },
+}
+
+/// A 16550-compatible UART
+#[derive(Debug, Clone, Copy)]
+pub struct Port {
+    /// Where the registers of the UART are
+    pub registers: Registers,
+
+    /// Baud rate to configure, or `None` to keep the firmware's configuration
+    pub baud: Option<u32>,
+
+    /// Frequency of the input clock of the UART in Hz
+    pub clock: u32,
+}
+
+impl Port {
+    /// Returns the hardcoded port at I/O port `addr`
+    pub const fn fallback(addr: u16) -> Self {
+        Self {
+            registers: Registers::Io(addr),
+            baud:      Some(FALLBACK_BAUD),
+            clock:     DEFAULT_CLOCK,
+        }
+    }
+
+    /// Returns the console UART described by the SPCR, if it's one we can
+    /// drive
+    pub fn from_spcr(spcr: &Spcr) -> Option<Self> {
+        let gas = spcr.base_address;
+        if !spcr.is_16550() || !gas.is_present() { return None; }
+
+        let registers = match gas.address_space {
+            acpi::ADDRESS_SPACE_IO => {
+                Registers::Io(u16::try_from(gas.address).ok()?)
+            },
+            acpi::ADDRESS_SPACE_MEMORY => {
+                // Anything but byte and dword registers is made up
+                let width = gas.access_bytes();
+                if width != 1 && width != 4 { return None; }
+                Registers::Mmio { base: gas.address as usize, width }
+            },
+            _ => return None,
+        };
+
+        Some(Self {
+            registers,
+            baud:  spcr.baud_rate(),
+            clock: spcr.clock_frequency().unwrap_or(DEFAULT_CLOCK),
+        })
+    }
+
+    /// Read the register `reg`
+    unsafe fn read(&self, reg: usize) -> u8 {
+        match self.registers {
+            Registers::Io(base) => unsafe {
+                in8((base + reg as u16) as *const u16)
+            },
+            Registers::Mmio { base, width: 4 } => unsafe {
+                core::ptr::read_volatile((base + reg * 4) as *const u32) as u8
+            },
+            Registers::Mmio { base, width } => unsafe {
+                core::ptr::read_volatile((base + reg * width) as *const u8)
+            },
+        }
+    }
+
+    /// Write `val` to the register `reg`
+    unsafe fn write(&self, reg: usize, val: u8) {
+        match self.registers {
+            Registers::Io(base) => unsafe {
+                out8((base + reg as u16) as *const u16, val)
+            },
+            Registers::Mmio { base, width: 4 } => unsafe {
+                core::ptr::write_volatile((base + reg * 4) as *mut u32,
+                                          val as u32)
+            },
+            Registers::Mmio { base, width } => unsafe {
+                core::ptr::write_volatile((base + reg * width) as *mut u8, val)
+            },
+        }
+    }
+
+    /// Configure the UART to 8n1 at its baud rate, with interrupts disabled
+    fn init(&self) {
+        unsafe {
+            // Disable all interrupts
+            self.write(INTERRUPT_ENABLE, 0x00);
+
+            // Leave the rest to the firmware if it configured the UART
+            let Some(baud) = self.baud else { return; };
+
+            // Enable DLAB (set baud divisor)
+            self.write(LINE_CONTROL, 0x80);
+
+            // Divisor = clock / (16 * baud); low byte and high byte,
+            // respectively
+            let divisor = (self.clock / (16 * baud)).clamp(1, 0xFFFF);
+            self.write(DATA, divisor as u8);
+            self.write(INTERRUPT_ENABLE, (divisor >> 8) as u8);
+
+            // 8 bits, no parity, one stop bit
+            self.write(LINE_CONTROL, 0x03);
+
+            // IRQs disabled, RTS/DSR set
+            self.write(MODEM_CONTROL, 0x03);
+        }
+    }
+
+    /// Read a byte if one is available
+    fn read_byte(&self) -> Option<u8> {
+        unsafe {
+            // Check if there is a byte available.
+            // If yes, read and return it
+            if (self.read(LINE_STATUS) & 1) != 0 {
+                return Some(self.read(DATA));
+            }
+        }
+        None
+    }
+
+    /// Write a byte
+    fn write_byte(&self, byte: u8) {
+        unsafe {
+            // Wait for the transmit to be empty
+            while self.read(LINE_STATUS) & 0x20 == 0 {};
+
+            // Write the byte
+            self.write(DATA, byte);
+        }
+    }
+}
+
+/// The serial ports `print!()` writes to
+pub struct Serial {
+    /// The ports in use
+    ports: [Option<Port>; MAX_PORTS],
+}
 
 impl Serial {
-    /// Initialize the serial ports at addresses [`PORT_ADDRESSES`] on the
-    /// system to 28800n1.
+    /// Initialize the console UART described by the SPCR, or the serial ports
+    /// at addresses [`PORT_ADDRESSES`] on the system to 28800n1 if there's no
+    /// usable SPCR.
     ///
-    /// Panics if the serial driver is already initialized
+    /// Does nothing if the serial driver is already initialized
     pub fn init() {
         // Get the lock to the global driver
         let mut driver = SERIAL_DRIVER.lock();
@@ -36,60 +208,54 @@ impl Serial {
         // Make sure we're not re-initializing
         if driver.is_some() { return; }
 
-        // Go through each COM port and initialize it
-        for port in PORT_ADDRESSES.iter() {
-            unsafe {
-                // Disable all interrupts
-                out8(port.offset(1), 0x00);
-
-                // Enable DLAB (set baud divisor)
-                out8(port.offset(3), 0x80);
+        // Prefer the UART the firmware tells us about
+        let spcr = acpi::Acpi::new()
+            .and_then(|acpi| acpi.find::<Spcr>())
+            .ok()
+            .and_then(Port::from_spcr);
+        let ports = match spcr {
+            Some(port) => {
+                let mut ports = [None; MAX_PORTS];
+                ports[0] = Some(port);
+                ports
+            },
+            None => PORT_ADDRESSES.map(|addr| Some(Port::fallback(addr))),
+        };
 
-                // Divisor = 115200 / this; low byte and high byte, respectively
-                out8(port.offset(0), 0x04);
-                out8(port.offset(1), 0x00);
-
-                // 8 bits, no parity, one stop bit
-                out8(port.offset(3), 0x03);
-
-                // IRQs disabled, RTS/DSR set
-                out8(port.offset(4), 0x03);
-            }
+        // Go through each port and initialize it
+        for port in ports.iter().flatten() {
+            port.init();
         }
 
         // Save the initialized driver
-        *driver = Some(Self);
+        *driver = Some(Self { ports });
     }
 
-    /// Read a byte from the first COM port that has a byte available
-    pub fn read_byte(&mut self) -> Option<u8> {
-        // Iterate through the devices
-        for &port in PORT_ADDRESSES.iter() {
-            unsafe {
-                // Check if there is a byte available.
-                // If yes, read and return it
-                if (in8(port.offset(5)) & 1) != 0 {
-                    return Some(in8(port));
-                }
-            }
-        }
+    /// Use the serial ports at addresses [`PORT_ADDRESSES`] as the firmware
+    /// configured them, without consulting ACPI or reprogramming them.
+    ///
+    /// Meant for reporting errors before the system table can be trusted,
+    /// when there's no other way to get output. Does nothing if the serial
+    /// driver is already initialized.
+    pub fn init_early() {
+        let mut driver = SERIAL_DRIVER.lock();
+        if driver.is_some() { return; }
 
-        // No bytes to read
-        None
+        let ports = PORT_ADDRESSES.map(|addr| Some(Port {
+            baud: None,
+            ..Port::fallback(addr)
+        }));
+        *driver = Some(Self { ports });
     }
 
-    /// Write a byte to a COM port
-    fn write_byte(&mut self, port: *const u16, byte: u8) {
-        // Check if this port exists
-        if let Some(&port) = PORT_ADDRESSES.iter().find(|&&x| x == port) {
-            unsafe {
-                // Wait for the transmit to be empty
-                while in8(port.offset(5)) & 0x20 == 0 {};
+    /// Returns the ports in use
+    pub fn ports(&self) -> impl Iterator<Item = &Port> {
+        self.ports.iter().flatten()
+    }
 
-                // Write the byte
-                out8(port, byte);
-            }
-        }
+    /// Read a byte from the first port that has a byte available
+    pub fn read_byte(&mut self) -> Option<u8> {
+        self.ports().find_map(|port| port.read_byte())
     }
 
     /// Write bytes to all mapped serial devices
@@ -97,12 +263,12 @@ impl Serial {
         // Iterate through the bytes
         for &byte in bytes {
             // Write the byte to all mapped serial devices
-            for &port in PORT_ADDRESSES.iter() {
+            for port in self.ports.iter().flatten() {
                 // Handle newlines correctly
-                if byte == b'\n' { self.write_byte(port, b'\r'); }
+                if byte == b'\n' { port.write_byte(b'\r'); }
 
                 // Write the byte
-                self.write_byte(port, byte);
+                port.write_byte(byte);
             }
         }
     }
@@ -117,35 +283,3 @@ impl Write for Serial {
         Ok(())
     }
 }
-
-/// Serial `print!()` support
-#[macro_export] macro_rules! print {
-    ($($arg:tt)*) => {
-        let _ = <$crate::serial::Serial as core::fmt::Write>::write_fmt(
-            &mut $crate::serial::Serial, format_args!($($arg)*));
-    }
-}
-
-/// Dummy type to implement the `Write` trait on -- used for serial shattering
-pub struct SerialShatter;
-
-impl Write for SerialShatter {
-    fn write_str(&mut self, string: &str) -> core::fmt::Result {
-        unsafe {
-            let serial = SERIAL_DRIVER.shatter();
-            if let Some(serial) = &mut *serial {
-                serial.write(string.as_bytes());
-            }
-        }
-        Ok(())
-    }
-}
-
-/// Serial `print!()` that shatters the serial driver lock on print and
-/// as such is unsafe. Meant to be used in panics
-#[macro_export] macro_rules! print_shatter {
-    ($($arg:tt)*) => {
-        let _ = <$crate::serial::Serial as core::fmt::Write>::write_fmt(
-            &mut $crate::serial::Serial, format_args!($($arg)*));
-    }
-}
@@ -0,0 +1,357 @@
+//! Application processors
+//!
+//! The APs are started through EFI_MP_SERVICES_PROTOCOL before the boot
+//! services are exited. Each of them switches to a stack of its own, which
+//! isn't handed to the memory manager, and parks in a spin loop waiting for
+//! work to show up in its mailbox.
+//!
+//! Some firmware, including EDK2 and thus OVMF, sends INIT-SIPI-SIPI to every
+//! AP while exiting the boot services and keeps them halted from then on. The
+//! parked APs keep a heartbeat, so [`check_parked()`] can tell which of them
+//! are still ours.
+
+use core::ffi::c_void;
+use core::hint::spin_loop;
+use core::sync::atomic::{ AtomicU32, AtomicU64, AtomicUsize, Ordering };
+use crate::{ cpu, efi };
+use crate::efi::mp::MpServices;
+use crate::spinlock::SpinLock;
+
+/// Maximum number of logical processors we keep track of
+pub const MAX_CPUS: usize = 64;
+
+/// Size of the stack of every AP in bytes
+pub const AP_STACK_SIZE: usize = 64 * 1024;
+
+/// Number of microseconds an AP gets to park itself after being started
+const AP_START_TIMEOUT: usize = 100_000;
+
+/// Number of spins [`check_parked()`] waits for a heartbeat
+const HEARTBEAT_SPINS: usize = 1 << 20;
+
+/// The processors found by [`start_aps()`]
+static PROCESSORS: SpinLock<Processors> = SpinLock::new(Processors::new());
+
+/// The mailbox of every processor, indexed by its firmware processor number
+static MAILBOXES: [Mailbox; MAX_CPUS] = [const { Mailbox::new() }; MAX_CPUS];
+
+// States of a mailbox
+
+/// The processor was never started
+const OFFLINE: u32 = 0;
+
+/// The processor was started, but hasn't parked yet
+const STARTING: u32 = 1;
+
+/// The processor is spinning, waiting for work
+const PARKED: u32 = 2;
+
+/// Someone is putting work into the mailbox
+const CLAIMED: u32 = 3;
+
+/// The processor has work to do or is doing it
+const BUSY: u32 = 4;
+
+/// The processor stopped responding, most likely taken back by the firmware
+const LOST: u32 = 5;
+
+/// Errors returned by the SMP routines
+#[derive(Debug)]
+pub enum Error {
+    /// There's no MP services protocol
+    NoMpServices(efi::status::Error),
+
+    /// A call to the MP services failed
+    MpServicesFailed(efi::status::Error),
+
+    /// Couldn't allocate the stack of an AP
+    AllocationFailed(efi::status::Error),
+
+    /// The processor doesn't exist
+    NoSuchCpu,
+
+    /// The processor isn't parked in our loop
+    NotParked,
+}
+
+/// State of a processor as seen by the kernel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    /// Not running kernel code; either the BSP, disabled or never started
+    Offline,
+
+    /// Waiting for work
+    Parked,
+
+    /// Running work handed to it with [`run_on()`]
+    Busy,
+
+    /// Started once, but stopped responding
+    Lost,
+}
+
+/// Work passed from the BSP to an AP
+struct Mailbox {
+    /// One of the mailbox states
+    state: AtomicU32,
+
+    /// The `fn(usize)` to run
+    func: AtomicUsize,
+
+    /// The argument to pass to `func`
+    arg: AtomicUsize,
+
+    /// Top of the stack of the processor
+    stack: AtomicUsize,
+
+    /// The event the processor was started with, which the firmware holds on
+    /// to for as long as it runs
+    event: AtomicUsize,
+
+    /// Incremented by the processor every time it checks for work
+    heartbeat: AtomicU64,
+}
+
+impl Mailbox {
+    /// Returns an empty mailbox
+    const fn new() -> Self {
+        Self {
+            state:     AtomicU32::new(OFFLINE),
+            func:      AtomicUsize::new(0),
+            arg:       AtomicUsize::new(0),
+            stack:     AtomicUsize::new(0),
+            event:     AtomicUsize::new(0),
+            heartbeat: AtomicU64::new(0),
+        }
+    }
+}
+
+/// A logical processor
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    /// Processor number assigned by the firmware
+    pub number: usize,
+
+    /// The APIC ID of the processor
+    pub apic_id: u64,
+
+    /// Zero-based physical package number
+    pub package: u32,
+
+    /// Zero-based physical core number within the package
+    pub core: u32,
+
+    /// Zero-based logical thread number within the core
+    pub thread: u32,
+
+    /// Whether this is the BSP
+    pub bsp: bool,
+
+    /// Whether the firmware has the processor enabled
+    pub enabled: bool,
+}
+
+impl Processor {
+    /// Returns the current state of the processor
+    pub fn state(&self) -> State {
+        match MAILBOXES[self.number].state.load(Ordering::SeqCst) {
+            PARKED          => State::Parked,
+            CLAIMED | BUSY  => State::Busy,
+            LOST            => State::Lost,
+            _               => State::Offline,
+        }
+    }
+}
+
+/// The processors of the system
+#[derive(Debug, Clone, Copy)]
+pub struct Processors {
+    /// The processors, indexed by their processor number
+    cpus: [Option<Processor>; MAX_CPUS],
+}
+
+impl Processors {
+    /// Returns an empty list of processors
+    const fn new() -> Self {
+        Self { cpus: [None; MAX_CPUS] }
+    }
+
+    /// Returns an iterator over the processors
+    pub fn iter(&self) -> impl Iterator<Item = &Processor> {
+        self.cpus.iter().flatten()
+    }
+}
+
+/// Returns the processors found by [`start_aps()`]
+pub fn processors() -> Processors {
+    *PROCESSORS.lock()
+}
+
+/// Enumerate the processors and park every enabled AP in our spin loop.
+///
+/// Must be called on the BSP before the boot services are exited. Only the
+/// first [`MAX_CPUS`] processors are used. Returns the number of parked APs.
+pub fn start_aps(boot_svc: &efi::BootServices) -> Result<usize, Error> {
+    let mp = unsafe {
+        &mut *boot_svc.locate_protocol::<MpServices>()
+            .map_err(Error::NoMpServices)?
+    };
+
+    let (total, _) = mp.number_of_processors()
+        .map_err(Error::MpServicesFailed)?;
+    let bsp = mp.who_am_i().map_err(Error::MpServicesFailed)?;
+
+    let mut parked = 0;
+    for (number, mailbox) in MAILBOXES.iter().enumerate().take(total) {
+        // Save where the processor is
+        let info = mp.processor_info(number)
+            .map_err(Error::MpServicesFailed)?;
+        PROCESSORS.lock().cpus[number] = Some(Processor {
+            number,
+            apic_id: info.processor_id,
+            package: info.location.package,
+            core:    info.location.core,
+            thread:  info.location.thread,
+            bsp:     number == bsp,
+            enabled: info.is_enabled(),
+        });
+
+        // Only start the enabled APs
+        if number == bsp || !info.is_enabled() { continue; }
+
+        // Allocate the stack of the AP. It's `LoaderData`, so the memory
+        // manager never gets to hand it out
+        let stack = boot_svc.allocate_pages(efi::AllocateType::AnyPages,
+                                            efi::MemoryType::LoaderData,
+                                            AP_STACK_SIZE / 4096, 0)
+            .map_err(Error::AllocationFailed)?;
+        mailbox.stack.store(stack as usize + AP_STACK_SIZE, Ordering::SeqCst);
+        mailbox.state.store(STARTING, Ordering::SeqCst);
+
+        // Start the AP without waiting for it to return, which it never does.
+        // The event is never signaled either, but the firmware wants one to
+        // know we don't want to block. It holds on to the event while the AP
+        // runs, so the event lives in the mailbox and is never closed
+        let started = unsafe {
+            boot_svc.create_event(0, efi::TPL_CALLBACK, None,
+                                  core::ptr::null_mut())
+                .and_then(|event| {
+                    mailbox.event.store(event as usize, Ordering::SeqCst);
+                    mp.startup_this_ap(ap_entry, number, event,
+                                       number as *mut c_void)
+                })
+        };
+
+        // The AP never got to its stack if it wasn't started
+        if let Err(err) = started {
+            mailbox.state.store(OFFLINE, Ordering::SeqCst);
+            mailbox.stack.store(0, Ordering::SeqCst);
+            let _ = unsafe {
+                boot_svc.free_pages(stack, AP_STACK_SIZE / 4096)
+            };
+            return Err(Error::MpServicesFailed(err));
+        }
+
+        // Give the AP some time to park itself
+        for _ in 0..AP_START_TIMEOUT / 10 {
+            if mailbox.state.load(Ordering::SeqCst) == PARKED { break; }
+            boot_svc.stall(10);
+        }
+        if mailbox.state.load(Ordering::SeqCst) == PARKED { parked += 1; }
+    }
+
+    Ok(parked)
+}
+
+/// Entry point of an AP, running on a firmware stack
+extern "efiapi" fn ap_entry(arg: *mut c_void) {
+    let number = arg as usize;
+    let stack = MAILBOXES[number].stack.load(Ordering::SeqCst);
+    unsafe { cpu::switch_stack(stack, ap_park, number) };
+}
+
+/// Spin loop the APs are parked in, running on their own stack
+extern "sysv64" fn ap_park(number: usize) -> ! {
+    // There's nothing for us to handle interrupts with
+    unsafe { cpu::disable_interrupts() };
+
+    let mailbox = &MAILBOXES[number];
+    mailbox.state.store(PARKED, Ordering::SeqCst);
+
+    loop {
+        mailbox.heartbeat.fetch_add(1, Ordering::Relaxed);
+
+        // Run the work we've been handed, if any
+        if mailbox.state.load(Ordering::Acquire) == BUSY {
+            let func = mailbox.func.load(Ordering::Relaxed);
+            let func = unsafe {
+                core::mem::transmute::<usize, fn(usize)>(func)
+            };
+            func(mailbox.arg.load(Ordering::Relaxed));
+            mailbox.state.store(PARKED, Ordering::Release);
+        }
+
+        spin_loop();
+    }
+}
+
+/// Run `func(arg)` on the parked processor `number` without waiting for it to
+/// finish; see [`wait()`].
+///
+/// `func` must not call into the firmware.
+pub fn run_on(number: usize, func: fn(usize), arg: usize)
+        -> Result<(), Error> {
+    let mailbox = MAILBOXES.get(number).ok_or(Error::NoSuchCpu)?;
+
+    // Claim the mailbox so nobody else puts work into it
+    mailbox.state.compare_exchange(PARKED, CLAIMED, Ordering::SeqCst,
+                                   Ordering::SeqCst)
+        .map_err(|_| Error::NotParked)?;
+
+    mailbox.func.store(func as usize, Ordering::Relaxed);
+    mailbox.arg.store(arg, Ordering::Relaxed);
+    mailbox.state.store(BUSY, Ordering::Release);
+    Ok(())
+}
+
+/// Wait for the processor `number` to finish the work handed to it with
+/// [`run_on()`]
+pub fn wait(number: usize) -> Result<(), Error> {
+    let mailbox = MAILBOXES.get(number).ok_or(Error::NoSuchCpu)?;
+
+    loop {
+        match mailbox.state.load(Ordering::Acquire) {
+            PARKED         => return Ok(()),
+            CLAIMED | BUSY => spin_loop(),
+            _              => return Err(Error::NotParked),
+        }
+    }
+}
+
+/// Check which parked APs are still spinning in our loop, marking the others
+/// as lost. Meant to be called after the boot services are exited.
+///
+/// Returns the number of APs still parked and the number of lost ones.
+pub fn check_parked() -> (usize, usize) {
+    // Take a heartbeat of every parked AP, give them some time and check again
+    let before: [Option<u64>; MAX_CPUS] = core::array::from_fn(|idx| {
+        let mailbox = &MAILBOXES[idx];
+        (mailbox.state.load(Ordering::SeqCst) == PARKED)
+            .then(|| mailbox.heartbeat.load(Ordering::SeqCst))
+    });
+    for _ in 0..HEARTBEAT_SPINS { spin_loop(); }
+
+    let mut parked = 0;
+    let mut lost = 0;
+    for (mailbox, before) in MAILBOXES.iter().zip(before) {
+        let Some(before) = before else { continue; };
+
+        if mailbox.heartbeat.load(Ordering::SeqCst) != before {
+            parked += 1;
+        } else {
+            mailbox.state.store(LOST, Ordering::SeqCst);
+            lost += 1;
+        }
+    }
+    (parked, lost)
+}